@@ -0,0 +1,72 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rbkcrack::baseline::RecursiveAttack;
+use rbkcrack::{Attack, Zreduction};
+
+mod common;
+
+/// number of Z11 candidates attacked per iteration
+const CANDIDATES: usize = 32;
+
+fn attack(c: &mut Criterion) {
+    let data = common::synthetic(1 << 12);
+
+    let mut zr = Zreduction::new(&data.keystream);
+    zr.generate();
+    zr.reduce(|_, _| ());
+    let candidates = zr
+        .get_zi_2_32_vector()
+        .iter()
+        .take(CANDIDATES)
+        .cloned()
+        .collect::<Vec<_>>();
+    assert_eq!(
+        CANDIDATES,
+        candidates.len(),
+        "the synthetic input leaves too few Z values after reduction"
+    );
+    let index = zr.get_index() + 1 - Attack::SIZE;
+    let attack = Attack::new(&data.plain_text, &data.keystream, index);
+
+    let mut group = c.benchmark_group("attack");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(candidates.len() as u64));
+
+    // the recursive engine, one attack per candidate, as find_keys used to do
+    group.bench_function("recursive", |b| {
+        b.iter(|| {
+            candidates
+                .iter()
+                .filter(|&&z| {
+                    RecursiveAttack::new(&data.plain_text, &data.keystream, index).carry_out(z)
+                })
+                .count()
+        })
+    });
+
+    // the explicit-stack engine, still one attack per candidate
+    group.bench_function("carry_out", |b| {
+        b.iter(|| {
+            candidates
+                .iter()
+                .filter(|&&z| attack.clone().carry_out(z))
+                .count()
+        })
+    });
+
+    group.bench_function("carry_out_batch", |b| {
+        let mut attack = attack.clone();
+        b.iter(|| {
+            let mut found = 0;
+            attack.carry_out_batch(&candidates, |_, _| {
+                found += 1;
+                true
+            });
+            found
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, attack);
+criterion_main!(benches);
@@ -0,0 +1,58 @@
+//! 固定的合成输入, 不读文件, 保证不同 commit 之间的结果可以比较
+//!
+//! 比较两个 commit:
+//!
+//! ```sh
+//! git checkout old && cargo bench -- --save-baseline old
+//! git checkout new && cargo bench -- --baseline old
+//! ```
+
+#![allow(dead_code)]
+
+use rbkcrack::{Keys, KEYSTREAMTAB};
+
+/// keys used to encrypt the synthetic plaintext
+pub const KEYS: [u32; 3] = [0x8879_dfed, 0x1433_5b6b, 0x8dc5_8b53];
+
+/// Plaintext, ciphertext and keystream generated from fixed keys
+pub struct Synthetic {
+    pub plain_text: Vec<u8>,
+    pub cipher_text: Vec<u8>,
+    pub keystream: Vec<u8>,
+}
+
+/// pseudo random bytes, the same on every run
+pub fn bytes(len: usize) -> Vec<u8> {
+    let mut x = 0x1234_5678u32;
+    (0..len)
+        .map(|_| {
+            x = x.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (x >> 24) as u8
+        })
+        .collect()
+}
+
+/// encrypt len pseudo random bytes with KEYS
+pub fn synthetic(len: usize) -> Synthetic {
+    let plain_text = bytes(len);
+    let mut keys = KEYS.iter().cloned().collect::<Keys>();
+    let keystream = plain_text
+        .iter()
+        .map(|&p| {
+            let k = KEYSTREAMTAB.get_byte(keys.get_z());
+            keys.update(p);
+            k
+        })
+        .collect::<Vec<_>>();
+    let cipher_text = plain_text
+        .iter()
+        .zip(&keystream)
+        .map(|(&p, &k)| p ^ k)
+        .collect();
+
+    Synthetic {
+        plain_text,
+        cipher_text,
+        keystream,
+    }
+}
@@ -0,0 +1,31 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rbkcrack::Keys;
+
+mod common;
+
+/// number of bytes deciphered per iteration
+const LEN: usize = 1 << 20;
+
+fn decipher(c: &mut Criterion) {
+    let data = common::synthetic(LEN);
+
+    let mut group = c.benchmark_group("decipher");
+    group.throughput(Throughput::Bytes(LEN as u64));
+
+    // the same loop as the command line
+    group.bench_function("decipher", |b| {
+        let mut buffer = Vec::with_capacity(LEN);
+        b.iter(|| {
+            let mut keys = common::KEYS.iter().cloned().collect::<Keys>();
+            buffer.clear();
+            buffer.extend_from_slice(&data.cipher_text);
+            keys.decipher(&mut buffer);
+            assert_eq!(data.plain_text, buffer);
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, decipher);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rbkcrack::Keys;
+
+mod common;
+
+/// number of bytes processed per iteration
+const LEN: usize = 1 << 16;
+
+fn keys(c: &mut Criterion) {
+    let data = common::synthetic(LEN);
+
+    let mut group = c.benchmark_group("keys");
+    group.throughput(Throughput::Bytes(LEN as u64));
+
+    group.bench_function("update", |b| {
+        b.iter(|| {
+            let mut keys = common::KEYS.iter().cloned().collect::<Keys>();
+            for &p in &data.plain_text {
+                keys.update(p);
+            }
+            keys.get_z()
+        })
+    });
+
+    group.bench_function("update_backword", |b| {
+        let mut end = common::KEYS.iter().cloned().collect::<Keys>();
+        for &p in &data.plain_text {
+            end.update(p);
+        }
+        b.iter(|| {
+            let mut keys = end.clone();
+            for &c in data.cipher_text.iter().rev() {
+                keys.update_backword(c);
+            }
+            keys.get_z()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, keys);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rbkcrack::Zreduction;
+
+mod common;
+
+fn zreduction(c: &mut Criterion) {
+    let data = common::synthetic(1 << 12);
+
+    let mut group = c.benchmark_group("zreduction");
+    group.sample_size(10);
+
+    group.bench_function("generate", |b| {
+        b.iter(|| {
+            let mut zr = Zreduction::new(&data.keystream);
+            zr.generate();
+            zr.size()
+        })
+    });
+
+    // the Z values are generated outside of the measurement
+    for &len in &[1 << 8, 1 << 10, 1 << 12] {
+        let keystream = &data.keystream[..len];
+        group.bench_with_input(BenchmarkId::new("reduce", len), &len, |b, _| {
+            b.iter_batched(
+                || {
+                    let mut zr = Zreduction::new(keystream);
+                    zr.generate();
+                    zr
+                },
+                |mut zr| {
+                    zr.reduce(|_, _| ());
+                    zr.size()
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, zreduction);
+criterion_main!(benches);
@@ -0,0 +1,31 @@
+#![no_main]
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use rbkcrack::file::Bytes;
+use rbkcrack::plain::Fragment;
+use rbkcrack::{Data, Keys};
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    cipher_text: Vec<u8>,
+    fragments: Vec<(i32, Vec<u8>)>,
+    keys: (u32, u32, u32),
+}
+
+fuzz_target!(|input: Input| {
+    let fragments = input
+        .fragments
+        .into_iter()
+        .map(|(offset, data)| Fragment {
+            offset,
+            data: Bytes::from(data),
+        })
+        .collect::<Vec<_>>();
+    if let Ok(data) = Data::from_fragments(Bytes::from(input.cipher_text), &fragments) {
+        assert_eq!(data.plain_text.len(), data.keystream.len());
+        let (x, y, z) = input.keys;
+        let mut keys = Keys::new();
+        keys.set_keys(x, y, z);
+        data.check_keys(&keys);
+    }
+});
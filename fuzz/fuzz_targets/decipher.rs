@@ -0,0 +1,36 @@
+#![no_main]
+use flate2::write::DeflateDecoder;
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use rbkcrack::{Keys, KEYSTREAMTAB};
+use std::io::{sink, Write};
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    keys: (u32, u32, u32),
+    cipher_text: Vec<u8>,
+    inflate: bool,
+}
+
+fuzz_target!(|input: Input| {
+    let (x, y, z) = input.keys;
+    let mut keys = Keys::new();
+    keys.set_keys(x, y, z);
+
+    let mut plain_text = input.cipher_text.clone();
+    keys.decipher(&mut plain_text);
+
+    // 倒着走回去应该回到初始状态
+    for (&c, &p) in input.cipher_text.iter().zip(&plain_text).rev() {
+        keys.update_backword(c);
+        assert_eq!(c ^ p, KEYSTREAMTAB.get_byte(keys.get_z()));
+    }
+    assert_eq!((x, y, z), (keys.get_x(), keys.get_y(), keys.get_z()));
+
+    if input.inflate && plain_text.len() > 12 {
+        let mut decoder = DeflateDecoder::new(sink());
+        let _ = decoder
+            .write_all(&plain_text[12..])
+            .and_then(|_| decoder.finish());
+    }
+});
@@ -0,0 +1,32 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rbkcrack::file::{read_central_directory, read_zip_entry, scan_local_headers};
+use std::io::Cursor;
+use std::path::PathBuf;
+
+fn archive_path() -> PathBuf {
+    std::env::temp_dir().join(format!("rbkcrack-fuzz-{}.zip", std::process::id()))
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut names = vec![];
+    // 单个文件以及两个分卷的情况
+    for volume_starts in &[&[0][..], &[0, data.len() as u64 / 2][..]] {
+        if let Ok(entries) = read_central_directory(&mut Cursor::new(data), volume_starts) {
+            names.extend(entries.into_iter().map(|entry| entry.info.name));
+        }
+    }
+    if let Ok(entries) = scan_local_headers(&mut Cursor::new(data)) {
+        names.extend(entries.into_iter().map(|entry| entry.info.name));
+    }
+
+    // 读取条目数据需要真实的文件
+    if !names.is_empty() {
+        let path = archive_path();
+        std::fs::write(&path, data).unwrap();
+        for name in &names {
+            let _ = read_zip_entry(path.to_str().unwrap(), name, usize::MAX);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+});
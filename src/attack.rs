@@ -1,159 +1,266 @@
 use crate::crc32_tab::CRC32TAB;
-use crate::data::Data;
 use crate::keys::Keys;
 use crate::keystream_tab::KEYSTREAMTAB;
 use crate::mult_tab::{MultTab, MULTTAB};
 use crate::utils::*;
+use alloc::vec::Vec;
+use core::mem;
+
+/// Partial Y-list, down to the current level i
+#[derive(Debug, Clone, Copy, Default)]
+struct YState {
+    /// (Yi - 1) * mult^-1
+    fy: u32,
+    /// LSB(Xj) for j > i, at index j - 4
+    xs: [u8; 8],
+}
 
+/// Search state of the attack
+///
+/// The Z-lists are explored with an explicit stack: the state of the current level is kept in
+/// local variables and saved in `z_stack[i]` and `zim1_10_32[i]` when going to the next level.
+/// Once a Z-list is complete, all the Y11 guesses are completed together one level at a time,
+/// in two buffers reused from a Z11 candidate to another (a few MB, allocated on first use).
 #[derive(Debug, Clone)]
 pub struct Attack<'a> {
     z_list: [u32; 12],
     y_list: [u32; 12],
     x_list: [u32; 12],
-    data: &'a Data,
+    /// remaining Z{i-1}[2,16) candidates of the levels above the current one
+    z_stack: [&'static [u32]; 12],
+    /// Z{i-1}[10,32) at level i
+    zim1_10_32: [u32; 12],
+    /// Y-lists being completed, one level at a time
+    y_states: Vec<YState>,
+    /// Y-lists of the next level
+    y_next: Vec<YState>,
+    plain_text: &'a [u8],
+    keystream: &'a [u8],
     index: usize,
 }
 
 impl<'a> Attack<'a> {
     pub const SIZE: usize = 12;
 
-    pub fn new(data: &Data, index: usize) -> Attack {
+    /// Attack on the known plaintext and its keystream, using the 12 bytes starting at index
+    pub fn new(plain_text: &'a [u8], keystream: &'a [u8], index: usize) -> Attack<'a> {
         Attack {
             z_list: [0; 12],
             y_list: [0; 12],
             x_list: [0; 12],
-            data,
+            z_stack: [&[]; 12],
+            zim1_10_32: [0; 12],
+            y_states: Vec::new(),
+            y_next: Vec::new(),
+            plain_text,
+            keystream,
             index,
         }
     }
 
+    /// carry out the attack for a Z11[2,32) value
+    /// **return** true if it leads to a solution, get_keys then gives the keys
     pub fn carry_out(&mut self, z11_2_32: u32) -> bool {
         self.z_list[11] = z11_2_32;
-        self.explore_z_lists(11)
+        self.explore_z_lists()
     }
 
-    pub fn get_keys(&self) -> Keys {
-        let mut keys = Keys::new();
-        keys.set_keys(self.x_list[7], self.y_list[7], self.z_list[7]);
-
-        // println!("({})", self.data.ciphertext[0]);
-        for &i in self.data.cipher_text
-            [0..(Data::HEADER_SIZE + self.data.offset as usize + self.index + 7)]
-            .iter()
-            .rev()
-        {
-            // println!("{}", i);
-            keys.update_backword(i);
+    /// carry out the attack for each Z11[2,32) value of candidates,
+    /// found is called with the index of the candidate on each solution
+    /// and the batch stops as soon as it returns false
+    /// **return** the number of candidates processed
+    pub fn carry_out_batch<F>(&mut self, candidates: &[u32], mut found: F) -> usize
+    where
+        F: FnMut(usize, &Attack<'a>) -> bool,
+    {
+        for (n, &z11_2_32) in candidates.iter().enumerate() {
+            if self.carry_out(z11_2_32) && !found(n, self) {
+                return n + 1;
+            }
         }
+        candidates.len()
+    }
 
+    /// return the keys at the beginning of cipher_text, plain_start being the position of the
+    /// known plaintext in cipher_text
+    pub fn get_keys(&self, cipher_text: &[u8], plain_start: usize) -> Keys {
+        self.get_keys_at(cipher_text, plain_start, 0)
+    }
+
+    /// return the keys at the given position of cipher_text, plain_start being the position of
+    /// the known plaintext in cipher_text
+    pub fn get_keys_at(&self, cipher_text: &[u8], plain_start: usize, position: usize) -> Keys {
+        let mut keys = Keys::new();
+        keys.set_keys(self.x_list[7], self.y_list[7], self.z_list[7]);
+        keys.seek(cipher_text, plain_start + self.index + 7, position);
         keys
     }
 
-    fn explore_z_lists(&mut self, i: i32) -> bool {
-        if i != 0 {
-            // the Z-list is not complete so generate Z{i-1}[2,32) values
-            let i = i as usize;
+    /// **return** Z{i-1}[10,32) and the Z{i-1}[2,16) candidates of level i
+    #[inline]
+    fn z_candidates(&self, i: usize) -> (u32, &'static [u32]) {
+        // get Z{i-1}[10,32) from CRC32^-1
+        let zim1_10_32 = CRC32TAB.get_zim1_10_32(self.z_list[i]);
 
-            // get Z{i-1}[10,32) from CRC32^-1
-            let zim1_10_32 = CRC32TAB.get_zim1_10_32(self.z_list[i]);
+        // get Z{i-1}[2,16) values from keystream byte k{i-1} and Z{i-1}[10,16)
+        let candidates =
+            KEYSTREAMTAB.get_zi_2_16_vector(self.keystream[self.index + i - 1], zim1_10_32);
+        (zim1_10_32, candidates)
+    }
 
-            // get Z{i-1}[2,16) values from keystream byte k{i-1} and Z{i-1}[10,16)
-            for &zim1_2_16 in KEYSTREAMTAB
-                .get_zi_2_16_vector(self.data.keystream[self.index as usize + i - 1], zim1_10_32)
-            {
-                // add Z{i-1}[2,32) to the Z-list
-                self.z_list[i - 1] = zim1_10_32 | zim1_2_16;
+    fn explore_z_lists(&mut self) -> bool {
+        // state of the current level, saved on the stack when going to the next level
+        let mut i = 11;
+        let (mut zim1_10_32, mut candidates) = self.z_candidates(i);
+
+        loop {
+            // take the next candidate of this level, or go back to the previous one
+            let zim1_2_16 = match candidates.split_first() {
+                Some((&zim1_2_16, rest)) => {
+                    candidates = rest;
+                    zim1_2_16
+                }
+                None if i == 11 => return false,
+                None => {
+                    i += 1;
+                    zim1_10_32 = self.zim1_10_32[i];
+                    candidates = self.z_stack[i];
+                    continue;
+                }
+            };
 
-                // find Zi[0,2) from Crc32^1
-                self.z_list[i] &= MASK_2_32;
-                self.z_list[i] |= (CRC32TAB.crc32inv(self.z_list[i], 0) ^ self.z_list[i - 1]) >> 8;
+            // add Z{i-1}[2,32) to the Z-list
+            self.z_list[i - 1] = zim1_10_32 | zim1_2_16;
 
-                // get Y{i+1}[24,32)
-                if i < 11 {
-                    self.y_list[i + 1] = CRC32TAB.get_yi_24_32(self.z_list[i + 1], self.z_list[i]);
-                }
+            // find Zi[0,2) from Crc32^1
+            self.z_list[i] &= MASK_2_32;
+            self.z_list[i] |= (CRC32TAB.crc32inv(self.z_list[i], 0) ^ self.z_list[i - 1]) >> 8;
 
-                if self.explore_z_lists(i as i32 - 1) {
-                    // println!("{}: 1 true", i);
-                    return true;
-                }
+            // get Y{i+1}[24,32)
+            if i < 11 {
+                self.y_list[i + 1] = CRC32TAB.get_yi_24_32(self.z_list[i + 1], self.z_list[i]);
             }
-            // println!("{}: 1 false", i);
-            false
-        } else {
-            // the Z-list is complete so iterate over possible Y values
-
-            // guess Y11[8,24) and keep prod == (Y11[8,32) - 1) * mult^-1
-            let mut prod =
-                (MULTTAB.get_multinv(msb(self.y_list[11])) << 24).wrapping_sub(MultTab::MULTINV);
-            for y11_8_24 in (0..(1 << 24)).step_by(1 << 8) {
-                // get possible Y11[0,8) values
-                for &y11_0_8 in
-                    MULTTAB.get_msb_prod_fiber3(msb(self.y_list[10]).wrapping_sub(msb(prod)))
-                {
-                    // filter Y11[0,8) using Y10[24,32)
-                    if prod + MULTTAB.get_multinv(y11_0_8) - (self.y_list[10] & MASK_24_32)
-                        <= MAXDIFF_0_24
-                    {
-                        self.y_list[11] =
-                            u32::from(y11_0_8) | y11_8_24 | (self.y_list[11] & MASK_24_32);
-                        if self.explore_y_lists(11) {
-                            // println!("{}: 2 true", i);
-                            return true;
-                        }
-                    }
-                }
 
-                prod = prod.wrapping_add(MultTab::MULTINV << 8);
+            if i > 1 {
+                // the Z-list is not complete so generate Z{i-2}[2,32) values
+                self.zim1_10_32[i] = zim1_10_32;
+                self.z_stack[i] = candidates;
+                i -= 1;
+                let next = self.z_candidates(i);
+                zim1_10_32 = next.0;
+                candidates = next.1;
+            } else if self.explore_y11() {
+                // the Z-list is complete so iterate over possible Y values
+                return true;
             }
-            // println!("{}: 2 false", i);
-            false
         }
     }
 
-    fn explore_y_lists(&mut self, i: i32) -> bool {
-        if i != 3 {
-            // the Y-list is not complete so generate Y{i-1} values
-            let i = i as usize;
-            let fy: u32 = (self.y_list[i as usize] - 1).wrapping_mul(MultTab::MULTINV);
-            let ffy: u32 = (fy - 1).wrapping_mul(MultTab::MULTINV);
-
-            // get possible LSB(Xi)
-            for &xi_0_8 in
-                MULTTAB.get_msb_prod_fiber2(msb(ffy.wrapping_sub(self.y_list[i - 2] & MASK_24_32)))
-            {
-                // compute corresponding Y{i-1}
-                let yim1 = fy - u32::from(xi_0_8);
-
-                // filter values with Y{i-2}[24,32)
-                if ffy
-                    .wrapping_sub(MULTTAB.get_multinv(xi_0_8))
-                    .wrapping_sub(self.y_list[i - 2] & MASK_24_32)
-                    <= MAXDIFF_0_24
-                    && msb(yim1) == msb(self.y_list[i - 1])
-                {
-                    // add Y{i-1} to the Y-list
-                    self.y_list[i as usize - 1] = yim1;
-
-                    // set Xi value
-                    self.x_list[i as usize] = u32::from(xi_0_8);
-
-                    if self.explore_y_lists(i as i32 - 1) {
-                        return true;
-                    }
+    fn explore_y11(&mut self) -> bool {
+        let y10_24_32 = self.y_list[10] & MASK_24_32;
+
+        // every guess is written and only kept if it passes the filter, which avoids
+        // unpredictable branches (a fiber has at most 4 elements)
+        if self.y_states.len() < 4 << 16 {
+            self.y_states.resize(4 << 16, YState::default());
+        }
+        let mut n = 0;
+
+        // guess Y11[8,24) and keep prod == (Y11[8,32) - 1) * mult^-1
+        let mut prod =
+            (MULTTAB.get_multinv(msb(self.y_list[11])) << 24).wrapping_sub(MultTab::MULTINV);
+        for _ in 0..(1 << 16) {
+            // get possible Y11[0,8) values
+            for &y11_0_8 in MULTTAB.get_msb_prod_fiber3(msb(y10_24_32).wrapping_sub(msb(prod))) {
+                // (Y11 - 1) * mult^-1
+                let fy = prod.wrapping_add(MULTTAB.get_multinv(y11_0_8));
+
+                // filter Y11[0,8) using Y10[24,32)
+                self.y_states[n] = YState { fy, xs: [0; 8] };
+                n += usize::from(fy.wrapping_sub(y10_24_32) <= MAXDIFF_0_24);
+            }
+
+            prod = prod.wrapping_add(MultTab::MULTINV << 8);
+        }
+
+        self.explore_y_states(n)
+    }
+
+    /// complete the Y-list from Y11 down to Y3
+    #[cfg(all(test, feature = "cli"))]
+    fn explore_y_lists(&mut self) -> bool {
+        let fy = self.y_list[11].wrapping_sub(1).wrapping_mul(MultTab::MULTINV);
+        self.y_states.clear();
+        self.y_states.push(YState { fy, xs: [0; 8] });
+        self.explore_y_states(1)
+    }
+
+    /// complete the Y-lists of the len first y_states, one level at a time
+    fn explore_y_states(&mut self, mut len: usize) -> bool {
+        for i in (4..=11).rev() {
+            let yim1_24_32 = self.y_list[i - 1] & MASK_24_32;
+            let yim2_24_32 = self.y_list[i - 2] & MASK_24_32;
+
+            // same as in explore_y11, a fiber has at most 3 elements
+            if self.y_next.len() < len * 3 {
+                self.y_next.resize(len * 3, YState::default());
+            }
+            let mut n = 0;
+            for state in &self.y_states[..len] {
+                let ffy = state.fy.wrapping_sub(1).wrapping_mul(MultTab::MULTINV);
+
+                // get possible LSB(Xi)
+                for &xi_0_8 in MULTTAB.get_msb_prod_fiber2(msb(ffy.wrapping_sub(yim2_24_32))) {
+                    // compute corresponding Y{i-1} and (Y{i-1} - 1) * mult^-1
+                    let yim1 = state.fy.wrapping_sub(u32::from(xi_0_8));
+                    let fyim1 = ffy.wrapping_sub(MULTTAB.get_multinv(xi_0_8));
+
+                    let next = &mut self.y_next[n];
+                    next.fy = fyim1;
+                    next.xs = state.xs;
+                    next.xs[i - 4] = xi_0_8;
+
+                    // filter values with Y{i-2}[24,32) and Y{i-1}[24,32)
+                    n += usize::from(
+                        (fyim1.wrapping_sub(yim2_24_32) <= MAXDIFF_0_24)
+                            & (msb(yim1) == msb(yim1_24_32)),
+                    );
                 }
             }
 
-            false
-        } else {
-            self.test_x_list()
+            mem::swap(&mut self.y_states, &mut self.y_next);
+            len = n;
+            if len == 0 {
+                return false;
+            }
+        }
+
+        // the Y-lists are complete so test the X-lists
+        for j in 0..len {
+            let state = self.y_states[j];
+            self.y_list[3] = state.fy.wrapping_mul(MultTab::MULT).wrapping_add(1);
+            for i in 4..=11 {
+                self.x_list[i] = u32::from(state.xs[i - 4]);
+            }
+
+            if self.test_x_list() {
+                // fill the Y-list from Y3
+                for i in 4..=11 {
+                    self.y_list[i] = self.y_list[i - 1]
+                        .wrapping_add(u32::from(state.xs[i - 4]))
+                        .wrapping_mul(MultTab::MULT)
+                        .wrapping_add(1);
+                }
+                return true;
+            }
         }
+        false
     }
 
     fn test_x_list(&mut self) -> bool {
         // compute X7
         for i in 5..=7 {
-            self.x_list[i] = (CRC32TAB.crc32(self.x_list[i-1], self.data.plain_text[self.index+i-1])
+            self.x_list[i] = (CRC32TAB.crc32(self.x_list[i-1], self.plain_text[self.index+i-1])
                 & MASK_8_32) // discard the LSB
                 | u32::from(lsb(self.x_list[i])); // set the LSB
         }
@@ -162,7 +269,7 @@ impl<'a> Attack<'a> {
 
         // compare 4 LSB(Xi) obtained from plaintext with those from the X-list
         for i in 8..=11 {
-            x = CRC32TAB.crc32(x, self.data.plain_text[self.index + i - 1]);
+            x = CRC32TAB.crc32(x, self.plain_text[self.index + i - 1]);
             if lsb(x) != lsb(self.x_list[i]) {
                 //println!("4");
                 return false;
@@ -172,7 +279,7 @@ impl<'a> Attack<'a> {
         // compute X3
         let mut x = self.x_list[7];
         for i in (3..=6).rev() {
-            x = CRC32TAB.crc32inv(x, self.data.plain_text[self.index + i]);
+            x = CRC32TAB.crc32inv(x, self.plain_text[self.index + i]);
         }
 
         // check that X3 fits with Y1[26,32)
@@ -191,11 +298,10 @@ impl<'a> Attack<'a> {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "cli"))]
 mod tests {
     use super::Attack;
-    use super::Data;
-    use crate::Arguments;
+    use crate::{Arguments, Data};
 
     #[test]
     fn test_x_list() {
@@ -207,7 +313,7 @@ mod tests {
             ..Default::default()
         })
         .unwrap();
-        let mut attack = Attack::new(&data, 735115);
+        let mut attack = Attack::new(&data.plain_text, &data.keystream, 735115);
         attack.x_list = [
             2, 64, 347029520, 21996, 207, 3988292578, 881025314, 2807276851, 77, 60, 9, 187,
         ];
@@ -220,7 +326,7 @@ mod tests {
             1744967186, 3351227042, 4039650542, 237715486, 282349850,
         ];
 
-        assert_eq!(true, attack.test_x_list());
+        assert!(attack.test_x_list());
     }
 
     #[test]
@@ -233,7 +339,7 @@ mod tests {
             ..Default::default()
         })
         .unwrap();
-        let mut attack = Attack::new(&data, 735115);
+        let mut attack = Attack::new(&data.plain_text, &data.keystream, 735115);
         attack.x_list = [
             2, 64, 3414458384, 22000, 207, 3988292578, 881025314, 2807276851, 77, 60, 9, 187,
         ];
@@ -246,7 +352,7 @@ mod tests {
             1744967186, 3351227042, 4039650542, 237715486, 282349850,
         ];
 
-        assert_eq!(true, attack.explore_y_lists(11));
+        assert!(attack.explore_y_lists());
     }
 
     #[test]
@@ -259,15 +365,25 @@ mod tests {
             ..Default::default()
         })
         .unwrap();
-        let mut attack = Attack::new(&data, 735115);
+        let mut attack = Attack::new(&data.plain_text, &data.keystream, 735115);
         attack.x_list[7] = 2807276851;
         attack.y_list[7] = 2433410890;
         attack.z_list[7] = 1744967186;
 
-        let keys = attack.get_keys();
+        let keys = attack.get_keys(&data.cipher_text, Data::HEADER_SIZE);
 
         assert_eq!(0x8879dfed, keys.get_x());
         assert_eq!(0x14335b6b, keys.get_y());
         assert_eq!(0x8dc58b53, keys.get_z());
+        // 攻击所在位置的密钥就是求出的 X, Y, Z
+        let position = Data::HEADER_SIZE + 735115 + 7;
+        let at_index = attack.get_keys_at(&data.cipher_text, Data::HEADER_SIZE, position);
+        assert_eq!(
+            (2807276851, 2433410890, 1744967186),
+            (at_index.get_x(), at_index.get_y(), at_index.get_z())
+        );
+        let mut rewound = at_index.clone();
+        rewound.seek(&data.cipher_text, position, 0);
+        assert_eq!(keys, rewound);
     }
 }
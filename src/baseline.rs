@@ -0,0 +1,236 @@
+//! The recursive attack engine as it was before `Attack` used an explicit stack and batches,
+//! kept unchanged (apart from wrapping arithmetic) so that the benches can compare both
+//! engines on the same lookup tables
+//!
+//! Not part of the public API.
+
+use crate::crc32_tab::CRC32TAB;
+use crate::keys::Keys;
+use crate::keystream_tab::KEYSTREAMTAB;
+use crate::mult_tab::{MultTab, MULTTAB};
+use crate::utils::*;
+
+#[derive(Debug, Clone)]
+pub struct RecursiveAttack<'a> {
+    z_list: [u32; 12],
+    y_list: [u32; 12],
+    x_list: [u32; 12],
+    plain_text: &'a [u8],
+    keystream: &'a [u8],
+    index: usize,
+}
+
+impl<'a> RecursiveAttack<'a> {
+    /// Attack on the known plaintext and its keystream, using the 12 bytes starting at index
+    pub fn new(plain_text: &'a [u8], keystream: &'a [u8], index: usize) -> RecursiveAttack<'a> {
+        RecursiveAttack {
+            z_list: [0; 12],
+            y_list: [0; 12],
+            x_list: [0; 12],
+            plain_text,
+            keystream,
+            index,
+        }
+    }
+
+    pub fn carry_out(&mut self, z11_2_32: u32) -> bool {
+        self.z_list[11] = z11_2_32;
+        self.explore_z_lists(11)
+    }
+
+    /// return the keys at the beginning of cipher_text, plain_start being the position of the
+    /// known plaintext in cipher_text
+    pub fn get_keys(&self, cipher_text: &[u8], plain_start: usize) -> Keys {
+        let mut keys = Keys::new();
+        keys.set_keys(self.x_list[7], self.y_list[7], self.z_list[7]);
+
+        for &i in cipher_text[0..(plain_start + self.index + 7)].iter().rev() {
+            keys.update_backword(i);
+        }
+
+        keys
+    }
+
+    fn explore_z_lists(&mut self, i: i32) -> bool {
+        if i != 0 {
+            // the Z-list is not complete so generate Z{i-1}[2,32) values
+            let i = i as usize;
+
+            // get Z{i-1}[10,32) from CRC32^-1
+            let zim1_10_32 = CRC32TAB.get_zim1_10_32(self.z_list[i]);
+
+            // get Z{i-1}[2,16) values from keystream byte k{i-1} and Z{i-1}[10,16)
+            for &zim1_2_16 in
+                KEYSTREAMTAB.get_zi_2_16_vector(self.keystream[self.index + i - 1], zim1_10_32)
+            {
+                // add Z{i-1}[2,32) to the Z-list
+                self.z_list[i - 1] = zim1_10_32 | zim1_2_16;
+
+                // find Zi[0,2) from Crc32^1
+                self.z_list[i] &= MASK_2_32;
+                self.z_list[i] |= (CRC32TAB.crc32inv(self.z_list[i], 0) ^ self.z_list[i - 1]) >> 8;
+
+                // get Y{i+1}[24,32)
+                if i < 11 {
+                    self.y_list[i + 1] = CRC32TAB.get_yi_24_32(self.z_list[i + 1], self.z_list[i]);
+                }
+
+                if self.explore_z_lists(i as i32 - 1) {
+                    return true;
+                }
+            }
+            false
+        } else {
+            // the Z-list is complete so iterate over possible Y values
+
+            // guess Y11[8,24) and keep prod == (Y11[8,32) - 1) * mult^-1
+            let mut prod =
+                (MULTTAB.get_multinv(msb(self.y_list[11])) << 24).wrapping_sub(MultTab::MULTINV);
+            for y11_8_24 in (0..(1 << 24)).step_by(1 << 8) {
+                // get possible Y11[0,8) values
+                for &y11_0_8 in
+                    MULTTAB.get_msb_prod_fiber3(msb(self.y_list[10]).wrapping_sub(msb(prod)))
+                {
+                    // filter Y11[0,8) using Y10[24,32)
+                    if prod
+                        .wrapping_add(MULTTAB.get_multinv(y11_0_8))
+                        .wrapping_sub(self.y_list[10] & MASK_24_32)
+                        <= MAXDIFF_0_24
+                    {
+                        self.y_list[11] =
+                            u32::from(y11_0_8) | y11_8_24 | (self.y_list[11] & MASK_24_32);
+                        if self.explore_y_lists(11) {
+                            return true;
+                        }
+                    }
+                }
+
+                prod = prod.wrapping_add(MultTab::MULTINV << 8);
+            }
+            false
+        }
+    }
+
+    fn explore_y_lists(&mut self, i: i32) -> bool {
+        if i != 3 {
+            // the Y-list is not complete so generate Y{i-1} values
+            let i = i as usize;
+            let fy = self.y_list[i]
+                .wrapping_sub(1)
+                .wrapping_mul(MultTab::MULTINV);
+            let ffy = fy.wrapping_sub(1).wrapping_mul(MultTab::MULTINV);
+
+            // get possible LSB(Xi)
+            for &xi_0_8 in
+                MULTTAB.get_msb_prod_fiber2(msb(ffy.wrapping_sub(self.y_list[i - 2] & MASK_24_32)))
+            {
+                // compute corresponding Y{i-1}
+                let yim1 = fy.wrapping_sub(u32::from(xi_0_8));
+
+                // filter values with Y{i-2}[24,32)
+                if ffy
+                    .wrapping_sub(MULTTAB.get_multinv(xi_0_8))
+                    .wrapping_sub(self.y_list[i - 2] & MASK_24_32)
+                    <= MAXDIFF_0_24
+                    && msb(yim1) == msb(self.y_list[i - 1])
+                {
+                    // add Y{i-1} to the Y-list
+                    self.y_list[i - 1] = yim1;
+
+                    // set Xi value
+                    self.x_list[i] = u32::from(xi_0_8);
+
+                    if self.explore_y_lists(i as i32 - 1) {
+                        return true;
+                    }
+                }
+            }
+
+            false
+        } else {
+            self.test_x_list()
+        }
+    }
+
+    fn test_x_list(&mut self) -> bool {
+        // compute X7
+        for i in 5..=7 {
+            self.x_list[i] = (CRC32TAB.crc32(self.x_list[i - 1], self.plain_text[self.index + i - 1])
+                & MASK_8_32) // discard the LSB
+                | u32::from(lsb(self.x_list[i])); // set the LSB
+        }
+
+        let mut x = self.x_list[7];
+
+        // compare 4 LSB(Xi) obtained from plaintext with those from the X-list
+        for i in 8..=11 {
+            x = CRC32TAB.crc32(x, self.plain_text[self.index + i - 1]);
+            if lsb(x) != lsb(self.x_list[i]) {
+                return false;
+            }
+        }
+
+        // compute X3
+        let mut x = self.x_list[7];
+        for i in (3..=6).rev() {
+            x = CRC32TAB.crc32inv(x, self.plain_text[self.index + i]);
+        }
+
+        // check that X3 fits with Y1[26,32)
+        let y1_26_32 = CRC32TAB.get_yi_24_32(self.z_list[1], self.z_list[0]) & MASK_26_32;
+        if self.y_list[3]
+            .wrapping_sub(1)
+            .wrapping_mul(MultTab::MULTINV)
+            .wrapping_sub(u32::from(lsb(x)))
+            .wrapping_sub(1)
+            .wrapping_mul(MultTab::MULTINV)
+            .wrapping_sub(y1_26_32)
+            > MAXDIFF_0_26
+        {
+            return false;
+        }
+
+        // all tests passed so the keys are found
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RecursiveAttack;
+    use crate::attack::Attack;
+    use crate::keys::Keys;
+    use crate::keystream_tab::KEYSTREAMTAB;
+    use crate::utils::MASK_2_32;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn same_as_attack() {
+        let mut keys = Keys::new();
+        keys.set_keys(0x1234_5678, 0x2345_6789, 0x3456_7890);
+        let plain_text = (0..Attack::SIZE as u8).collect::<Vec<_>>();
+        let (mut keystream, mut z11) = (Vec::new(), 0);
+        for &p in &plain_text {
+            z11 = keys.get_z() & MASK_2_32;
+            keystream.push(KEYSTREAMTAB.get_byte(keys.get_z()));
+            keys.update(p);
+        }
+        let cipher_text = plain_text
+            .iter()
+            .zip(&keystream)
+            .map(|(p, k)| p ^ k)
+            .collect::<Vec<_>>();
+
+        let mut attack = Attack::new(&plain_text, &keystream, 0);
+        let mut recursive = RecursiveAttack::new(&plain_text, &keystream, 0);
+        assert!(attack.carry_out(z11));
+        assert!(recursive.carry_out(z11));
+        assert_eq!(
+            attack.get_keys(&cipher_text, 0),
+            recursive.get_keys(&cipher_text, 0)
+        );
+        for &z in &[z11 ^ 0x0400_0000, z11 ^ 0x0000_0400] {
+            assert_eq!(attack.carry_out(z), recursive.carry_out(z));
+        }
+    }
+}
@@ -1,9 +1,6 @@
 use crate::utils::*;
-use lazy_static::lazy_static;
 
-lazy_static! {
-    pub static ref CRC32TAB: Crc32Tab = Crc32Tab::new();
-}
+pub static CRC32TAB: Crc32Tab = Crc32Tab::new();
 
 /// Lookup tables for CRC32 related computations
 pub struct Crc32Tab {
@@ -14,32 +11,44 @@ pub struct Crc32Tab {
 impl Crc32Tab {
     const CRCPOL: u32 = 0xedb8_8320;
 
-    pub fn new() -> Crc32Tab {
+    /// Compute the lookup tables, evaluated at compile time for `CRC32TAB`
+    pub const fn new() -> Crc32Tab {
         let mut crc32tab = Crc32Tab {
             crctab: [0; 256],
             crcinvtab: [0; 256],
         };
 
-        for b in 0..256 {
+        let mut b = 0;
+        while b < 256 {
             let mut crc = b;
 
             // compute crc32 from the original definition
-            for _ in 0..8 {
+            let mut i = 0;
+            while i < 8 {
                 crc = if crc & 1 != 0 {
                     crc >> 1 ^ Crc32Tab::CRCPOL
                 } else {
                     crc >> 1
                 };
+                i += 1;
             }
 
             // fill lookup tables
             crc32tab.crctab[b as usize] = crc;
             crc32tab.crcinvtab[msb(crc) as usize] = crc << 8 ^ b;
+            b += 1;
         }
 
         crc32tab
     }
 
+    /// return the CRC32^-1 lookup table, indexed by the MSB
+    #[cfg(all(feature = "std", target_arch = "x86_64"))]
+    #[inline]
+    pub fn get_crcinvtab(&self) -> &[u32; 256] {
+        &self.crcinvtab
+    }
+
     /// return CRC32 using a lookup table
     #[inline]
     pub fn crc32(&self, pval: u32, b: u8) -> u32 {
@@ -67,11 +76,20 @@ impl Crc32Tab {
 
 #[cfg(test)]
 mod tests {
-    use super::Crc32Tab;
+    use super::{Crc32Tab, CRC32TAB};
+    use proptest::prelude::*;
 
     #[test]
     fn get_zim1_10_32() {
         let instance = Crc32Tab::new();
         assert_eq!(1838198784, instance.get_zim1_10_32(33555384));
     }
+
+    proptest! {
+        #[test]
+        fn crc32inv_inverts_crc32(crc: u32, b: u8) {
+            prop_assert_eq!(crc, CRC32TAB.crc32inv(CRC32TAB.crc32(crc, b), b));
+            prop_assert_eq!(crc, CRC32TAB.crc32(CRC32TAB.crc32inv(crc, b), b));
+        }
+    }
 }
@@ -1,49 +1,189 @@
 use crate::attack::Attack;
+#[cfg(feature = "cli")]
+use crate::deflate::DeflateHint;
 use crate::file::*;
-use crate::Arguments;
+use crate::keys::Keys;
+use crate::keystream_tab::KEYSTREAMTAB;
+use crate::plain::Fragment;
+#[cfg(feature = "cli")]
+use crate::plain::NestedHeader;
+#[cfg(feature = "cli")]
+use crate::{plain, Arguments};
 use failure::{format_err, Error};
 use log::debug;
 
 #[derive(Debug, Clone)]
 pub struct Data {
-    pub cipher_text: Vec<u8>,
-    pub plain_text: Vec<u8>,
+    pub cipher_text: Bytes,
+    pub plain_text: Bytes,
     pub keystream: Vec<u8>,
     pub offset: i32,
+    /// known plaintext bytes outside of plain_text, as (offset, byte), used to check the keys
+    pub extra_plain: Vec<(i32, u8)>,
 }
 
 impl Data {
     pub const HEADER_SIZE: usize = 12;
 
+    #[cfg(feature = "cli")]
     pub fn new(args: &Arguments) -> Result<Data, Error> {
-        let offset = args.offset.unwrap_or(0);
-        // check that offset is not too small
-        if Data::HEADER_SIZE as i32 + offset < 0 {
-            return Err(format_err!("offset is too small"));
+        let stdin_count = [
+            (&args.plain_file, &args.plain_zip),
+            (&args.cipher_file, &args.cipher_zip),
+            (&args.plain_spec, &None),
+        ]
+        .iter()
+        .filter(|(file, zip)| zip.is_none() && file.as_deref() == Some("-"))
+        .count();
+        if stdin_count > 1 {
+            return Err(format_err!("only one input can be read from stdin"));
         }
 
-        let mut plain_text;
-        let mut cipher_text;
+        let mut fragments = vec![];
 
-        if args.auto_find {
-            let (a, b) = auto_load_file(
-                args.plain_zip.as_ref().unwrap(),
-                args.cipher_zip.as_ref().unwrap(),
-            )?;
-            plain_text = a;
-            cipher_text = b;
+        let cipher_text = if args.auto_find {
+            let (plain_zip, cipher_zip) = match (&args.plain_zip, &args.cipher_zip) {
+                (Some(plain_zip), Some(cipher_zip)) => (plain_zip, cipher_zip),
+                _ => {
+                    return Err(format_err!(
+                        "both zip files are needed to search automatically"
+                    ))
+                }
+            };
+            let (a, b) = auto_load_file(plain_zip, cipher_zip)?;
+            fragments.push(Fragment {
+                offset: args.offset.unwrap_or(0),
+                data: a,
+            });
+            b
         } else {
             // load known plaintext
-            plain_text = Self::load_plain(args)?;
+            if let Some(hint) = &args.deflate {
+                fragments.extend(Self::deflate_plain(args, hint)?);
+            } else if args.plain_file.is_some() {
+                fragments.push(Fragment {
+                    offset: args.offset.unwrap_or(0),
+                    data: Self::load_plain(args)?,
+                });
+            }
+            if let Some(spec) = &args.plain_spec {
+                fragments.extend(plain::read_spec_file(spec)?);
+            }
+            if let Some(nested) = &args.nested {
+                fragments.extend(Self::nested_plain(args, nested)?);
+            }
 
             // load ciphertext needed by the attack
-            cipher_text = Self::load_cipher(args, &plain_text)?;
+            let end = fragments
+                .iter()
+                .map(|f| f.offset as i64 + f.data.len() as i64)
+                .max()
+                .unwrap_or(0);
+            Self::load_cipher(args, (Data::HEADER_SIZE as i64 + end) as usize)?
+        };
+
+        Data::from_fragments(cipher_text, &fragments)
+    }
+
+    /// build the data from the ciphertext (including the encryption header) and known plaintext fragments
+    ///
+    /// the longest contiguous run of plaintext is used by the attack, the other bytes are used to check the keys
+    pub fn from_fragments(cipher_text: Bytes, fragments: &[Fragment]) -> Result<Data, Error> {
+        let mut order = (0..fragments.len())
+            .filter(|&i| !fragments[i].data.is_empty())
+            .collect::<Vec<_>>();
+        order.sort_by_key(|&i| fragments[i].offset);
+
+        // merge overlapping or adjacent fragments into runs of (start, end, fragment indices)
+        let mut runs: Vec<(i64, i64, Vec<usize>)> = vec![];
+        for i in order {
+            let fragment = &fragments[i];
+            let (start, end) = (
+                i64::from(fragment.offset),
+                i64::from(fragment.offset) + fragment.data.len() as i64,
+            );
+            // check that offset is not too small
+            if Data::HEADER_SIZE as i64 + start < 0 {
+                return Err(format_err!("offset is too small"));
+            }
+            // check that ciphertext is valid
+            if Data::HEADER_SIZE as i64 + end > cipher_text.len() as i64 {
+                return Err(format_err!("offset is too large"));
+            }
+
+            match runs.last_mut() {
+                Some(run) if run.1 >= start => {
+                    // check that overlapping bytes are the same
+                    for &j in &run.2 {
+                        let other = &fragments[j];
+                        let other_start = i64::from(other.offset);
+                        for k in
+                            start.max(other_start)..end.min(other_start + other.data.len() as i64)
+                        {
+                            if fragment.data[(k - start) as usize]
+                                != other.data[(k - other_start) as usize]
+                            {
+                                return Err(format_err!("conflicting plaintext at offset {}", k));
+                            }
+                        }
+                    }
+                    run.1 = run.1.max(end);
+                    run.2.push(i);
+                }
+                _ => runs.push((start, end, vec![i])),
+            }
+        }
+
+        // use the longest run for the attack
+        let best = runs
+            .iter()
+            .enumerate()
+            .max_by_key(|&(i, run)| (run.1 - run.0, std::cmp::Reverse(i)))
+            .map(|(i, _)| i);
+        let (offset, size) = best.map_or((0, 0), |i| (runs[i].0, runs[i].1 - runs[i].0));
+
+        // check that plaintext is big enough
+        if (size as usize) < Attack::SIZE {
+            return Err(format_err!("plaintext is too small"));
+        }
+
+        let mut plain_text = None;
+        let mut extra_plain = vec![];
+        for (i, (start, end, members)) in runs.into_iter().enumerate() {
+            if Some(i) == best && members.len() == 1 {
+                // avoid copying the plaintext
+                plain_text = Some(fragments[members[0]].data.clone());
+                continue;
+            }
+            let mut bytes = vec![0; (end - start) as usize];
+            for j in members {
+                let fragment = &fragments[j];
+                let k = (i64::from(fragment.offset) - start) as usize;
+                bytes[k..k + fragment.data.len()].copy_from_slice(&fragment.data);
+            }
+            if Some(i) == best {
+                plain_text = Some(Bytes::from(bytes));
+            } else {
+                extra_plain.extend((start..end).map(|k| k as i32).zip(bytes));
+            }
         }
+        let plain_text = plain_text.unwrap();
+        let offset = offset as i32;
+        debug!(
+            "plain size {} at offset {}, {} extra bytes",
+            size,
+            offset,
+            extra_plain.len()
+        );
 
         // compute keystream
         let keystream = plain_text
             .iter()
-            .zip(cipher_text.iter().skip(Data::HEADER_SIZE + offset as usize))
+            .zip(
+                cipher_text
+                    .iter()
+                    .skip((Data::HEADER_SIZE as i32 + offset) as usize),
+            )
             .map(|(x, y)| x ^ y)
             .collect();
         Ok(Data {
@@ -51,53 +191,196 @@ impl Data {
             plain_text,
             keystream,
             offset,
+            extra_plain,
         })
     }
 
+    /// check that keys at the beginning of the ciphertext decipher the extra plaintext
+    pub fn check_keys(&self, keys: &Keys) -> bool {
+        let mut keys = keys.clone();
+        let mut extra = self.extra_plain.iter().peekable();
+        for (i, &c) in self.cipher_text.iter().enumerate() {
+            let p = c ^ KEYSTREAMTAB.get_byte(keys.get_z());
+            match extra.peek() {
+                Some(&&(offset, b)) if offset + Data::HEADER_SIZE as i32 == i as i32 => {
+                    if b != p {
+                        return false;
+                    }
+                    extra.next();
+                }
+                None => break,
+                _ => (),
+            }
+            keys.update(p);
+        }
+        true
+    }
+
     /// load known plaintext
-    fn load_plain(args: &Arguments) -> Result<Vec<u8>, Error> {
-        let plain_size = args.plain_size.unwrap_or(std::usize::MAX);
+    #[cfg(feature = "cli")]
+    fn load_plain(args: &Arguments) -> Result<Bytes, Error> {
+        let plain_size = args.plain_size.unwrap_or(usize::MAX);
+        let plain_file = args.plain_file.as_ref().unwrap();
 
-        let plain_text =
-            if let (Some(zip_path), Some(entry_name)) = (&args.plain_zip, &args.plain_file) {
-                read_zip_entry(zip_path, entry_name, plain_size)?
-            } else {
-                read_raw_file(args.plain_file.as_ref().unwrap(), plain_size)?
-            };
-        debug!(
-            "loaded plain {}, size {}",
-            args.plain_file.as_ref().unwrap(),
-            plain_text.len()
-        );
-        // check that plaintext is big enough
-        if plain_text.len() < Attack::SIZE {
-            return Err(format_err!("plaintext is too small"));
-        }
+        let plain_text = if let Some(zip_path) = &args.plain_zip {
+            read_zip_entry(zip_path, plain_file, plain_size)?
+        } else if let Some(literal) = plain::parse_literal(plain_file)? {
+            Bytes::from(literal).prefix(plain_size)
+        } else {
+            read_raw_file(plain_file, plain_size)?
+        };
+        debug!("loaded plain {}, size {}", plain_file, plain_text.len());
         Ok(plain_text)
     }
 
-    /// load ciphertext needed by the attack
-    fn load_cipher(args: &Arguments, plain_text: &[u8]) -> Result<Vec<u8>, Error> {
-        let offset = args.offset.unwrap_or(0);
-        let to_read = Data::HEADER_SIZE + offset as usize + plain_text.len();
-        let cipher_text =
-            if let (Some(zip_path), Some(entry_name)) = (&args.cipher_zip, &args.cipher_file) {
-                read_zip_entry(zip_path, entry_name, to_read)?
-            } else {
-                read_raw_file(&args.cipher_file.as_ref().unwrap(), to_read)?
-            };
-        debug!(
-            "loaded cipher {}, size {}",
-            args.cipher_file.as_ref().unwrap(),
-            cipher_text.len()
-        );
+    /// guess the local header of the archive stored in the ciphertext
+    #[cfg(feature = "cli")]
+    fn nested_plain(args: &Arguments, nested: &NestedHeader) -> Result<Vec<Fragment>, Error> {
+        let outer = args.cipher_file.as_deref().unwrap_or("");
+        if let Some(zip_path) = &args.cipher_zip {
+            let entries = read_entries(zip_path)?;
+            let entry = entries
+                .iter()
+                .find(|entry| entry.info.name == outer)
+                .ok_or_else(|| format_err!("could not find {} in {}", outer, zip_path))?;
+            if entry.info.method != 0 {
+                return Err(format_err!(
+                    "{} is compressed, the archive inside is unknown",
+                    outer
+                ));
+            }
+        }
+        let header = nested.clone().or(NestedHeader::guess(outer));
+        debug!("nested header {:?}", header);
+        let mut fragments = header.fragments()?;
+        for fragment in &mut fragments {
+            fragment.offset += args.offset.unwrap_or(0);
+        }
+        Ok(fragments)
+    }
 
-        // check that ciphertext is valid
-        if plain_text.len() > cipher_text.len() {
-            return Err(format_err!("ciphertext is smaller than plaintext"));
-        } else if Data::HEADER_SIZE + offset as usize + plain_text.len() > cipher_text.len() {
-            return Err(format_err!("offset is too large"));
+    /// predict the deflate stream of the ciphertext, the known plaintext being its uncompressed content
+    #[cfg(feature = "cli")]
+    fn deflate_plain(args: &Arguments, hint: &DeflateHint) -> Result<Vec<Fragment>, Error> {
+        let prefix = match args.plain_file {
+            Some(_) => Self::load_plain(args)?,
+            None => Bytes::from(vec![]),
+        };
+        let mut data_size = None;
+        if let (Some(zip_path), Some(cipher_file)) = (&args.cipher_zip, &args.cipher_file) {
+            let entries = read_entries(zip_path)?;
+            if let Some(entry) = entries.iter().find(|entry| &entry.info.name == cipher_file) {
+                if entry.info.method != 8 {
+                    return Err(format_err!("{} is not deflated", cipher_file));
+                }
+                data_size = entry.compressed_size.checked_sub(Data::HEADER_SIZE as u64);
+            }
         }
+        let mut fragments = hint.fragments(data_size, &prefix)?;
+        for fragment in &mut fragments {
+            fragment.offset += args.offset.unwrap_or(0);
+        }
+        debug!("deflate {:?}, {} fragments", hint, fragments.len());
+        Ok(fragments)
+    }
+
+    /// load ciphertext needed by the attack
+    #[cfg(feature = "cli")]
+    fn load_cipher(args: &Arguments, to_read: usize) -> Result<Bytes, Error> {
+        let cipher_file = args
+            .cipher_file
+            .as_ref()
+            .ok_or_else(|| format_err!("the ciphertext file is needed"))?;
+        let cipher_text = if let Some(zip_path) = &args.cipher_zip {
+            read_zip_entry(zip_path, cipher_file, to_read)?
+        } else {
+            read_raw_file(cipher_file, to_read)?
+        };
+        debug!("loaded cipher {}, size {}", cipher_file, cipher_text.len());
         Ok(cipher_text)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Data;
+    use crate::file::Bytes;
+    use crate::plain::Fragment;
+
+    #[test]
+    fn from_fragments() {
+        let cipher_text = Bytes::from((0..64).collect::<Vec<u8>>());
+        let fragments = [
+            Fragment {
+                offset: -2,
+                data: Bytes::from(vec![0; 4]),
+            },
+            Fragment {
+                offset: 10,
+                data: Bytes::from(vec![1; 12]),
+            },
+            Fragment {
+                offset: 20,
+                data: Bytes::from(vec![1; 4]),
+            },
+        ];
+        let data = Data::from_fragments(cipher_text.clone(), &fragments).unwrap();
+        assert_eq!(10, data.offset);
+        assert_eq!(&[1; 14], &*data.plain_text);
+        assert_eq!(vec![(-2, 0), (-1, 0), (0, 0), (1, 0)], data.extra_plain);
+        assert_eq!((22..36).map(|c| c ^ 1).collect::<Vec<u8>>(), data.keystream);
+
+        let conflict = Fragment {
+            offset: 21,
+            data: Bytes::from(vec![2]),
+        };
+        assert!(
+            Data::from_fragments(cipher_text.clone(), &[fragments[1].clone(), conflict]).is_err()
+        );
+        let too_large = Fragment {
+            offset: 50,
+            data: Bytes::from(vec![0; 12]),
+        };
+        assert!(Data::from_fragments(cipher_text.clone(), &[too_large]).is_err());
+
+        // 明文可以从加密头开始
+        let in_header = Fragment {
+            offset: -4,
+            data: Bytes::from(vec![0; 16]),
+        };
+        let data = Data::from_fragments(cipher_text, &[in_header]).unwrap();
+        assert_eq!(-4, data.offset);
+        assert_eq!((8..24).collect::<Vec<u8>>(), data.keystream);
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn nested_plain() {
+        use crate::Arguments;
+
+        let nested = "auto".parse().unwrap();
+        let mut args = Arguments {
+            cipher_file: Some("inner.zip".into()),
+            ..Default::default()
+        };
+        let offsets = |args: &Arguments| {
+            Data::nested_plain(args, &nested)
+                .unwrap()
+                .iter()
+                .map(|f| f.offset)
+                .collect::<Vec<_>>()
+        };
+        let expected = offsets(&args);
+        assert_eq!(0, expected[0]);
+
+        // 内层压缩包前面还有 5 字节
+        args.offset = Some(5);
+        assert_eq!(
+            expected.iter().map(|o| o + 5).collect::<Vec<_>>(),
+            offsets(&args)
+        );
+
+        args.cipher_zip = Some("./example/cipher.zip".into());
+        assert!(Data::nested_plain(&args, &nested).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+//! 由 deflate 数据流的结构推测压缩后的明文
+//!
+//! 压缩过的条目的明文是 deflate 数据流, 一般无从得知, 但有两种情况下其中一部分是可以预测的:
+//! 压缩率为 0 (或数据无法压缩) 时的 stored block, 以及用固定 Huffman 编码的开头没有重复的文本
+
+use crate::file::Bytes;
+use crate::plain::Fragment;
+use failure::{format_err, Error};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// zlib 一个 stored block 最多存放的字节数
+pub const MAX_STORED: u16 = 0xffff;
+/// stored block 头的长度
+const STORED_HEADER_SIZE: u64 = 5;
+/// deflate 能找到的最短的重复
+const MIN_MATCH: usize = 3;
+
+/// 数据流的结构
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeflateHint {
+    /// 全部由 stored block 组成, size 为未压缩的大小, 未知时由压缩后的大小推算
+    Stored { size: Option<u64>, block_size: u16 },
+    /// 以固定 Huffman 编码的 block 开头, 已知明文为未压缩内容的开头
+    ///
+    /// last 为 None 时不知道是否是最后一个 block, size 为未压缩的大小
+    Fixed {
+        last: Option<bool>,
+        size: Option<u64>,
+    },
+}
+
+impl DeflateHint {
+    /// 生成压缩后的明文, data_size 为 deflate 数据流的长度, prefix 为已知的未压缩内容
+    pub fn fragments(&self, data_size: Option<u64>, prefix: &[u8]) -> Result<Vec<Fragment>, Error> {
+        match *self {
+            DeflateHint::Stored { size, block_size } => {
+                let size = match (size, data_size) {
+                    (Some(size), _) => size,
+                    (None, Some(data_size)) => stored_size(data_size, block_size)?,
+                    (None, None) => {
+                        return Err(format_err!(
+                            "the uncompressed size of the stored blocks is unknown"
+                        ))
+                    }
+                };
+                let mut fragments = stored_headers(size, block_size)?;
+                // 已知的内容直接位于第一个 block 头之后
+                let known = prefix.len().min(size.min(u64::from(block_size)) as usize);
+                if known > 0 {
+                    fragments.push(Fragment {
+                        offset: STORED_HEADER_SIZE as i32,
+                        data: Bytes::from(prefix[..known].to_vec()),
+                    });
+                }
+                Ok(fragments)
+            }
+            DeflateHint::Fixed { last, size } => {
+                if prefix.is_empty() {
+                    return Err(format_err!(
+                        "fixed Huffman blocks need the start of the uncompressed content"
+                    ));
+                }
+                let complete = size == Some(prefix.len() as u64);
+                Ok(fixed_block(prefix, last, complete).into_iter().collect())
+            }
+        }
+    }
+}
+
+/// stored block 的头: BFINAL 和 BTYPE (00) 补齐到一个字节, 然后是 LEN 和 NLEN
+pub fn stored_header(len: u16, last: bool) -> [u8; 5] {
+    let [lo, hi] = len.to_le_bytes();
+    [last as u8, lo, hi, !lo, !hi]
+}
+
+/// 未压缩大小为 size 的数据按 block_size 分为 stored block 后所有 block 头的位置
+pub fn stored_headers(size: u64, block_size: u16) -> Result<Vec<Fragment>, Error> {
+    let block_size = u64::from(block_size.max(1));
+    let count = size.div_ceil(block_size).max(1);
+    (0..count)
+        .map(|i| {
+            let len = (size - i * block_size).min(block_size) as u16;
+            let offset = i32::try_from(i * (block_size + STORED_HEADER_SIZE))
+                .map_err(|_| format_err!("stored blocks of {} bytes are too large", size))?;
+            Ok(Fragment {
+                offset,
+                data: Bytes::from(stored_header(len, i + 1 == count).to_vec()),
+            })
+        })
+        .collect()
+}
+
+/// 由 stored block 组成的长度为 data_size 的数据流的未压缩大小
+pub fn stored_size(data_size: u64, block_size: u16) -> Result<u64, Error> {
+    let block_size = u64::from(block_size.max(1));
+    let count = data_size.div_ceil(block_size + STORED_HEADER_SIZE).max(1);
+    data_size
+        .checked_sub(count * STORED_HEADER_SIZE)
+        .filter(|&size| size.div_ceil(block_size).max(1) == count)
+        .ok_or_else(|| format_err!("{} bytes cannot be made of stored blocks", data_size))
+}
+
+/// 把 prefix 当作字面量用固定 Huffman 编码, 返回其中确定的字节
+///
+/// prefix 中出现重复时压缩器可能会改用 (长度, 距离) 编码, 因此只编码到第一个可能的重复之前,
+/// last 未知时第一个字节的最低位不确定, 明文从第二个字节开始.
+/// complete 表示 prefix 就是全部内容, 此时最后一个 block 的结束符和补齐的 0 也是确定的
+pub fn fixed_block(prefix: &[u8], last: Option<bool>, complete: bool) -> Option<Fragment> {
+    let literals = literal_prefix(prefix, complete);
+    let mut writer = BitWriter::default();
+    writer.write(last.unwrap_or(false) as u32, 1);
+    writer.write(1, 2);
+    for &b in &prefix[..literals] {
+        let (code, len) = if b < 144 {
+            (0x30 + u32::from(b), 8)
+        } else {
+            (0x190 + u32::from(b) - 144, 9)
+        };
+        writer.write_code(code, len);
+    }
+    if literals == prefix.len() && complete && last == Some(true) {
+        // 结束符 256 的编码为 7 个 0, 然后用 0 补齐到整字节
+        writer.write_code(0, 7);
+        writer.write(0, (8 - writer.count) % 8);
+    }
+    let data = writer.bytes;
+
+    let start = if last.is_some() { 0 } else { 1 };
+    if data.len() <= start {
+        return None;
+    }
+    Some(Fragment {
+        offset: start as i32,
+        data: Bytes::from(data[start..].to_vec()),
+    })
+}
+
+/// prefix 开头一定会编码为字面量的字节数
+///
+/// 某个位置之后的 MIN_MATCH 个字节在前面出现过时压缩器就可能使用重复 (这里不考虑窗口大小),
+/// 不完整的 prefix 末尾的字节之后是什么未知, 只要已知的部分出现过就视为可能重复
+fn literal_prefix(prefix: &[u8], complete: bool) -> usize {
+    let mut seen = HashSet::new();
+    for i in 0..prefix.len() {
+        let end = (i + MIN_MATCH).min(prefix.len());
+        if (end - i == MIN_MATCH || !complete) && seen.contains(&prefix[i..end]) {
+            return i;
+        }
+        for len in 1..=end - i {
+            seen.insert(&prefix[i..i + len]);
+        }
+    }
+    prefix.len()
+}
+
+/// deflate 从每个字节的最低位开始写入
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    /// 写入 value 的低 len 位, 低位在前
+    fn write(&mut self, value: u32, len: u32) {
+        self.acc |= value << self.count;
+        self.count += len;
+        while self.count >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman 编码高位在前
+    fn write_code(&mut self, code: u32, len: u32) {
+        self.write(code.reverse_bits() >> (32 - len), len);
+    }
+}
+
+/// 解析 `--deflate` 的参数
+///
+/// `stored` 或 `fixed`, 后面可以跟逗号分隔的 `size=N` (未压缩的大小),
+/// stored 的 `block=N` (block 的最大长度) 以及 fixed 的 `last=0|1` (是否是最后一个 block)
+impl FromStr for DeflateHint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<DeflateHint, Error> {
+        let mut fields = s.split(',');
+        let mut hint = match fields.next().unwrap().trim() {
+            "stored" => DeflateHint::Stored {
+                size: None,
+                block_size: MAX_STORED,
+            },
+            "fixed" => DeflateHint::Fixed {
+                last: None,
+                size: None,
+            },
+            kind => return Err(format_err!("unknown deflate block type '{}'", kind)),
+        };
+        for field in fields {
+            let mut parts = field.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let value = parts
+                .next()
+                .ok_or_else(|| format_err!("expected key=value in '{}'", field))?;
+            let invalid = || format_err!("invalid value of {} '{}'", key, value);
+            match (&mut hint, key) {
+                (DeflateHint::Stored { size, .. }, "size")
+                | (DeflateHint::Fixed { size, .. }, "size") => {
+                    *size = Some(value.parse().map_err(|_| invalid())?)
+                }
+                (DeflateHint::Stored { block_size, .. }, "block") => {
+                    *block_size = value.parse().ok().filter(|&n| n > 0).ok_or_else(invalid)?
+                }
+                (DeflateHint::Fixed { last, .. }, "last") => {
+                    *last = Some(match value {
+                        "0" => false,
+                        "1" => true,
+                        _ => return Err(invalid()),
+                    })
+                }
+                _ => return Err(format_err!("unknown deflate field '{}'", key)),
+            }
+        }
+        Ok(hint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fixed_block, stored_headers, stored_size, DeflateHint};
+
+    #[test]
+    fn stored() {
+        let headers = stored_headers(10, 4)
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.offset, f.data.to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (0, vec![0, 4, 0, 0xfb, 0xff]),
+                (9, vec![0, 4, 0, 0xfb, 0xff]),
+                (18, vec![1, 2, 0, 0xfd, 0xff]),
+            ],
+            headers
+        );
+        assert_eq!(
+            vec![1, 0, 0, 0xff, 0xff],
+            stored_headers(0, 4).unwrap()[0].data.to_vec()
+        );
+
+        assert_eq!(10, stored_size(25, 4).unwrap());
+        assert_eq!(0, stored_size(5, 4).unwrap());
+        assert_eq!(65535 + 1, stored_size(65535 + 1 + 10, 65535).unwrap());
+        assert!(stored_size(23, 4).is_err());
+        assert!(stored_headers(1 << 32, 65535).is_err());
+        assert!(stored_size(3, 4).is_err());
+
+        let hint: DeflateHint = "stored,block=4".parse().unwrap();
+        let fragments = hint.fragments(Some(25), b"abcdef").unwrap();
+        assert_eq!(
+            (5, b"abcd".to_vec()),
+            (fragments[3].offset, fragments[3].data.to_vec())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "extract")]
+    fn fixed() {
+        use flate2::read::DeflateDecoder;
+        use std::io::Read;
+
+        let text = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>";
+        let block = fixed_block(text, Some(true), true).unwrap();
+        assert_eq!(0, block.offset);
+        let mut decoded = vec![];
+        DeflateDecoder::new(&*block.data)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(&text[..], &*decoded);
+
+        // 第二个 abc 可能被编码为重复
+        let start = fixed_block(b"abcdabcd", None, false).unwrap();
+        let complete = fixed_block(b"abcd", Some(false), true).unwrap();
+        assert_eq!(1, start.offset);
+        assert_eq!(&complete.data[1..], &*start.data);
+        assert_eq!(3, start.data.len());
+        assert!(fixed_block(b"a", None, false).is_none());
+
+        let hint: DeflateHint = "fixed,last=1,size=3".parse().unwrap();
+        let fragments = hint.fragments(None, b"abc").unwrap();
+        assert_eq!(
+            vec![0x4b, 0x4c, 0x4a, 0x06, 0x00],
+            fragments[0].data.to_vec()
+        );
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            DeflateHint::Stored {
+                size: Some(7),
+                block_size: 16384
+            },
+            "stored,size=7,block=16384".parse().unwrap()
+        );
+        assert_eq!(
+            DeflateHint::Fixed {
+                last: Some(false),
+                size: None
+            },
+            "fixed,last=0".parse().unwrap()
+        );
+        assert!("dynamic".parse::<DeflateHint>().is_err());
+        assert!("stored,last=1".parse::<DeflateHint>().is_err());
+        assert!("fixed,block=3".parse::<DeflateHint>().is_err());
+        assert!("stored,block=0".parse::<DeflateHint>().is_err());
+        assert!("fixed,last=2".parse::<DeflateHint>().is_err());
+    }
+}
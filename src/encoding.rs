@@ -0,0 +1,339 @@
+//! 密码和字典的字符编码
+//!
+//! ZipCrypto 只认字节, 非 ASCII 的密码在不同的压缩软件中会被编码为 UTF-8 或本地的代码页
+
+use core::fmt;
+use core::str::FromStr;
+use failure::{format_err, Error};
+use lazy_static::lazy_static;
+
+/// 单字节代码页 0x80 到 0xff 对应的字符, `\0` 表示未定义
+const CP437: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+const CP850: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜø£Ø×ƒáíóúñÑªº¿®¬½¼¡«»░▒▓│┤ÁÂÀ©╣║╗╝¢¥┐└┴┬├─┼ãÃ╚╔╩╦╠═╬¤ðÐÊËÈıÍÎÏ┘┌█▄¦Ì▀ÓßÔÒõÕµþÞÚÛÙýÝ¯´\u{ad}±‗¾¶§÷¸°¨·¹³²■\u{a0}";
+const CP852: &str = "ÇüéâäůćçłëŐőîŹÄĆÉĹĺôöĽľŚśÖÜŤťŁ×čáíóúĄąŽžĘę¬źČş«»░▒▓│┤ÁÂĚŞ╣║╗╝Żż┐└┴┬├─┼Ăă╚╔╩╦╠═╬¤đĐĎËďŇÍÎě┘┌█▄ŢŮ▀ÓßÔŃńňŠšŔÚŕŰýÝţ´\u{ad}˝˛ˇ˘§÷¸°¨˙űŘř■\u{a0}";
+const CP866: &str = "АБВГДЕЖЗИЙКЛМНОПРСТУФХЦЧШЩЪЫЬЭЮЯабвгдежзийклмноп░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀рстуфхцчшщъыьэюяЁёЄєЇїЎў°∙·√№¤■\u{a0}";
+const CP1250: &str = "€\0‚\0„…†‡\0‰Š‹ŚŤŽŹ\0‘’“”•–—\0™š›śťžź\u{a0}ˇ˘Ł¤Ą¦§¨©Ş«¬\u{ad}®Ż°±˛ł´µ¶·¸ąş»Ľ˝ľżŔÁÂĂÄĹĆÇČÉĘËĚÍÎĎĐŃŇÓÔŐÖ×ŘŮÚŰÜÝŢßŕáâăäĺćçčéęëěíîďđńňóôőö÷řůúűüýţ˙";
+const CP1251: &str = "ЂЃ‚ѓ„…†‡€‰Љ‹ЊЌЋЏђ‘’“”•–—\0™љ›њќћџ\u{a0}ЎўЈ¤Ґ¦§Ё©Є«¬\u{ad}®Ї°±Ііґµ¶·ё№є»јЅѕїАБВГДЕЖЗИЙКЛМНОПРСТУФХЦЧШЩЪЫЬЭЮЯабвгдежзийклмнопрстуфхцчшщъыьэюя";
+const CP1252: &str = "€\0‚ƒ„…†‡ˆ‰Š‹Œ\0Ž\0\0‘’“”•–—˜™š›œ\0žŸ\u{a0}¡¢£¤¥¦§¨©ª«¬\u{ad}®¯°±²³´µ¶·¸¹º»¼½¾¿ÀÁÂÃÄÅÆÇÈÉÊËÌÍÎÏÐÑÒÓÔÕÖ×ØÙÚÛÜÝÞßàáâãäåæçèéêëìíîïðñòóôõö÷øùúûüýþÿ";
+
+/// 单字节代码页的高半部分, 正反两个方向都只需查表
+struct CodePage {
+    chars: [char; 128],
+    /// 按字符排序的 (字符, 字节), 不含未定义的位置
+    bytes: Vec<(char, u8)>,
+}
+
+impl CodePage {
+    fn new(table: &str) -> CodePage {
+        let mut chars = ['\0'; 128];
+        for (c, t) in chars.iter_mut().zip(table.chars()) {
+            *c = t;
+        }
+        let mut bytes = (0x80..=0xff)
+            .zip(table.chars())
+            .filter(|&(_, c)| c != '\0')
+            .map(|(b, c)| (c, b))
+            .collect::<Vec<_>>();
+        bytes.sort_unstable();
+        CodePage { chars, bytes }
+    }
+
+    fn encode(&self, c: char) -> Option<u8> {
+        self.bytes
+            .binary_search_by_key(&c, |&(t, _)| t)
+            .ok()
+            .map(|i| self.bytes[i].1)
+    }
+
+    fn decode(&self, b: u8) -> Option<char> {
+        Some(self.chars[(b - 0x80) as usize]).filter(|&c| c != '\0')
+    }
+}
+
+lazy_static! {
+    /// 按 Encoding::ALL 的顺序, 只在第一次用到时建表
+    static ref CODE_PAGES: Vec<Option<CodePage>> = Encoding::ALL
+        .iter()
+        .map(|encoding| encoding.table().map(CodePage::new))
+        .collect();
+}
+
+/// 支持的字符编码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    /// 只用于读取字典
+    Utf16Le,
+    /// 只用于读取字典
+    Utf16Be,
+    Latin1,
+    /// DOS 美国, 许多压缩软件的默认 OEM 代码页
+    Cp437,
+    /// DOS 西欧
+    Cp850,
+    /// DOS 中欧
+    Cp852,
+    /// DOS 西里尔
+    Cp866,
+    /// Windows 中欧
+    Cp1250,
+    /// Windows 西里尔
+    Cp1251,
+    /// Windows 西欧
+    Cp1252,
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 11] = [
+        Encoding::Utf8,
+        Encoding::Utf16Le,
+        Encoding::Utf16Be,
+        Encoding::Latin1,
+        Encoding::Cp437,
+        Encoding::Cp850,
+        Encoding::Cp852,
+        Encoding::Cp866,
+        Encoding::Cp1250,
+        Encoding::Cp1251,
+        Encoding::Cp1252,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin1",
+            Encoding::Cp437 => "cp437",
+            Encoding::Cp850 => "cp850",
+            Encoding::Cp852 => "cp852",
+            Encoding::Cp866 => "cp866",
+            Encoding::Cp1250 => "cp1250",
+            Encoding::Cp1251 => "cp1251",
+            Encoding::Cp1252 => "cp1252",
+        }
+    }
+
+    /// 单字节代码页的高半部分
+    fn table(self) -> Option<&'static str> {
+        match self {
+            Encoding::Cp437 => Some(CP437),
+            Encoding::Cp850 => Some(CP850),
+            Encoding::Cp852 => Some(CP852),
+            Encoding::Cp866 => Some(CP866),
+            Encoding::Cp1250 => Some(CP1250),
+            Encoding::Cp1251 => Some(CP1251),
+            Encoding::Cp1252 => Some(CP1252),
+            _ => None,
+        }
+    }
+
+    fn code_page(self) -> Option<&'static CodePage> {
+        CODE_PAGES[self as usize].as_ref()
+    }
+
+    /// ASCII 字符是否编码为同样的单个字节, 只有这样的编码能用于密码
+    pub fn is_ascii_compatible(self) -> bool {
+        !matches!(self, Encoding::Utf16Le | Encoding::Utf16Be)
+    }
+
+    /// 编码 text, 有无法表示的字符时返回 None
+    pub fn encode(self, text: &str) -> Option<Vec<u8>> {
+        match self {
+            Encoding::Utf8 => Some(text.as_bytes().to_vec()),
+            Encoding::Utf16Le => Some(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+            Encoding::Utf16Be => Some(text.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+            Encoding::Latin1 => text
+                .chars()
+                .map(|c| {
+                    if (c as u32) < 0x100 {
+                        Some(c as u8)
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+            _ => {
+                let code_page = self.code_page().unwrap();
+                text.chars()
+                    .map(|c| {
+                        if c.is_ascii() {
+                            Some(c as u8)
+                        } else {
+                            code_page.encode(c)
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// 解码 bytes, 不是合法的编码时返回 None
+    pub fn decode(self, bytes: &[u8]) -> Option<String> {
+        let utf16 = |unit: fn([u8; 2]) -> u16| {
+            if bytes.len() % 2 != 0 {
+                return None;
+            }
+            let units = bytes.chunks(2).map(|c| unit([c[0], c[1]]));
+            char::decode_utf16(units)
+                .collect::<Result<String, _>>()
+                .ok()
+        };
+        match self {
+            Encoding::Utf8 => String::from_utf8(bytes.to_vec()).ok(),
+            Encoding::Utf16Le => utf16(u16::from_le_bytes),
+            Encoding::Utf16Be => utf16(u16::from_be_bytes),
+            Encoding::Latin1 => Some(bytes.iter().map(|&b| char::from(b)).collect()),
+            _ => {
+                let code_page = self.code_page().unwrap();
+                bytes
+                    .iter()
+                    .map(|&b| {
+                        if b < 0x80 {
+                            Some(char::from(b))
+                        } else {
+                            code_page.decode(b)
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// 编码中 ASCII 以外的字母
+    ///
+    /// 单字节代码页为高半部分中的字母, Unicode 编码取 Latin-1 补充和扩展 A 中的字母
+    pub fn letters(self) -> Vec<char> {
+        match self.table() {
+            Some(table) => table.chars().filter(|c| c.is_alphabetic()).collect(),
+            None if self == Encoding::Latin1 => ('\u{c0}'..='\u{ff}')
+                .filter(|c| c.is_alphabetic())
+                .collect(),
+            None => ('\u{c0}'..='\u{17f}')
+                .filter(|c| c.is_alphabetic())
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Encoding, Error> {
+        let name = s.to_ascii_lowercase().replace('_', "-");
+        let alias = match name.as_str() {
+            "utf8" => "utf-8",
+            "utf-16" | "utf16le" | "utf16" => "utf-16le",
+            "utf16be" => "utf-16be",
+            "latin-1" | "iso-8859-1" => "latin1",
+            "windows-1250" => "cp1250",
+            "windows-1251" => "cp1251",
+            "windows-1252" => "cp1252",
+            name => name,
+        };
+        Encoding::ALL
+            .iter()
+            .find(|encoding| encoding.name() == alias)
+            .cloned()
+            .ok_or_else(|| format_err!("unknown encoding '{}'", s))
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// 在每种可用于密码的编码下解码 bytes, 只保留不含控制字符的结果
+pub fn plausible_decodings(bytes: &[u8]) -> Vec<(Encoding, String)> {
+    Encoding::ALL
+        .iter()
+        .filter(|encoding| encoding.is_ascii_compatible())
+        .filter_map(|&encoding| {
+            encoding
+                .decode(bytes)
+                .filter(|text| !text.chars().any(char::is_control))
+                .map(|text| (encoding, text))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plausible_decodings, Encoding};
+
+    #[test]
+    fn tables() {
+        for encoding in &Encoding::ALL {
+            assert_eq!(*encoding, Encoding::ALL[*encoding as usize]);
+            if let Some(table) = encoding.table() {
+                assert_eq!(128, table.chars().count(), "{}", encoding);
+                // 每个字节都要能编码回来
+                for (b, c) in (0x80..=0xff).zip(table.chars()).filter(|&(_, c)| c != '\0') {
+                    assert_eq!(
+                        Some(b),
+                        encoding.code_page().unwrap().encode(c),
+                        "{}",
+                        encoding
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn encode_decode() {
+        let cases: &[(Encoding, &str, &[u8])] = &[
+            (Encoding::Utf8, "Grüße", b"Gr\xc3\xbc\xc3\x9fe"),
+            (Encoding::Utf16Le, "Aé", b"A\0\xe9\0"),
+            (Encoding::Utf16Be, "Aé", b"\0A\0\xe9"),
+            (Encoding::Latin1, "Grüße", b"Gr\xfc\xdfe"),
+            (Encoding::Cp437, "Grüße", b"Gr\x81\xe1e"),
+            (Encoding::Cp850, "Ø½", b"\x9d\xab"),
+            (Encoding::Cp852, "łč", b"\x88\x9f"),
+            (Encoding::Cp866, "Пароль", b"\x8f\xa0\xe0\xae\xab\xec"),
+            (Encoding::Cp1250, "Łódź", b"\xa3\xf3d\x9f"),
+            (Encoding::Cp1251, "Пароль", b"\xcf\xe0\xf0\xee\xeb\xfc"),
+            (Encoding::Cp1252, "€uro", b"\x80uro"),
+        ];
+        for &(encoding, text, bytes) in cases {
+            assert_eq!(Some(bytes.to_vec()), encoding.encode(text), "{}", encoding);
+            assert_eq!(
+                Some(text.to_owned()),
+                encoding.decode(bytes),
+                "{}",
+                encoding
+            );
+        }
+
+        assert_eq!(None, Encoding::Cp437.encode("€"));
+        assert_eq!(None, Encoding::Latin1.encode("€"));
+        assert_eq!(None, Encoding::Cp1252.decode(b"\x81"));
+        assert_eq!(None, Encoding::Utf8.decode(b"\xff"));
+        assert_eq!(None, Encoding::Utf16Le.decode(b"A"));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Encoding::Utf8, "UTF8".parse().unwrap());
+        assert_eq!(Encoding::Cp1252, "windows-1252".parse().unwrap());
+        assert_eq!(Encoding::Utf16Le, "utf-16".parse().unwrap());
+        for encoding in &Encoding::ALL {
+            assert_eq!(*encoding, encoding.name().parse().unwrap());
+        }
+        assert!("ebcdic".parse::<Encoding>().is_err());
+    }
+
+    #[test]
+    fn decodings() {
+        let decodings = plausible_decodings(b"Gr\xc3\xbc\xc3\x9fe");
+        assert!(decodings.contains(&(Encoding::Utf8, "Grüße".to_owned())));
+        assert!(decodings.contains(&(Encoding::Cp1252, "GrÃ¼ÃŸe".to_owned())));
+        assert!(decodings
+            .iter()
+            .all(|(encoding, _)| encoding.is_ascii_compatible()));
+        assert!(Encoding::Cp866.letters().contains(&'ж'));
+        assert!(Encoding::Utf8.letters().contains(&'ł'));
+    }
+}
@@ -0,0 +1,573 @@
+//! 用内部密钥解压整个压缩包
+//!
+//! 与改造过的 p7zip 一样, 不需要知道密码; 会还原目录, 修改时间, Unix 权限和符号链接
+
+use crate::data::Data;
+use crate::file::{self, find_extra_field, Encryption, EntryInfo, ZipEntry};
+use crate::keys::Keys;
+use crate::volume::MultiVolume;
+use chrono::{Local, NaiveDate, TimeZone};
+use failure::{format_err, Error};
+use flate2::write::DeflateDecoder;
+use flate2::CrcWriter;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 每次解密的字节数
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// extended timestamp extra field: flags(1) mtime(4) ...
+const EXTRA_TIMESTAMP: u16 = 0x5455;
+
+/// 条目在 dir 下的路径, 名字可能写到 dir 之外 (如含有 `..`) 时返回 None
+pub fn output_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    let mut path = dir.to_path_buf();
+    let mut empty = true;
+    for part in name.split(['/', '\\']) {
+        match part {
+            "" | "." => (),
+            ".." => return None,
+            // Windows 的盘符
+            _ if cfg!(windows) && part.contains(':') => return None,
+            _ => {
+                path.push(part);
+                empty = false;
+            }
+        }
+    }
+    if empty {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// 条目的修改时间, 优先使用 extended timestamp, 否则将 MS-DOS 时间按本地时间解释
+pub fn modified(info: &EntryInfo) -> Option<SystemTime> {
+    if let Some(field) = find_extra_field(&info.extra, EXTRA_TIMESTAMP) {
+        if field.len() >= 5 && field[0] & 1 != 0 {
+            let mtime = i32::from_le_bytes([field[1], field[2], field[3], field[4]]);
+            let secs = Duration::from_secs(u64::from(mtime.unsigned_abs()));
+            return if mtime < 0 {
+                UNIX_EPOCH.checked_sub(secs)
+            } else {
+                UNIX_EPOCH.checked_add(secs)
+            };
+        }
+    }
+
+    // date: year-1980(7) month(4) day(5), time: hour(5) minute(6) second/2(5)
+    let (date, time) = (u32::from(info.mod_date), u32::from(info.mod_time));
+    let datetime =
+        NaiveDate::from_ymd_opt(1980 + (date >> 9) as i32, date >> 5 & 0xf, date & 0x1f)?
+            .and_hms_opt(time >> 11, time >> 5 & 0x3f, (time & 0x1f) * 2)?;
+    let timestamp = Local.from_local_datetime(&datetime).earliest()?.timestamp();
+    UNIX_EPOCH.checked_add(Duration::from_secs(u64::try_from(timestamp).ok()?))
+}
+
+/// 解密并解压条目的数据, 写入 output 并校验 CRC32
+pub fn decode<W: Write>(
+    entry: &ZipEntry,
+    keys: &Keys,
+    data: &[u8],
+    output: W,
+) -> Result<(), Error> {
+    let (mut keys, data) = match entry.info.encryption() {
+        Encryption::None => (None, data),
+        Encryption::ZipCrypto => {
+            if data.len() < Data::HEADER_SIZE {
+                return Err(format_err!(
+                    "the data is shorter than the encryption header"
+                ));
+            }
+            let mut keys = keys.clone();
+            let mut header = data[..Data::HEADER_SIZE].to_vec();
+            keys.decipher(&mut header);
+            (Some(keys), &data[Data::HEADER_SIZE..])
+        }
+        encryption => {
+            return Err(format_err!(
+                "encrypted with {}, only ZipCrypto is supported",
+                encryption
+            ))
+        }
+    };
+    let hint = if keys.is_some() { ", wrong keys?" } else { "" };
+
+    let mut output = CrcWriter::new(output);
+    let mut copy = |output: &mut dyn Write| -> io::Result<()> {
+        let mut buffer = Vec::with_capacity(CHUNK_SIZE);
+        for chunk in data.chunks(CHUNK_SIZE) {
+            buffer.clear();
+            buffer.extend_from_slice(chunk);
+            if let Some(keys) = &mut keys {
+                keys.decipher(&mut buffer);
+            }
+            output.write_all(&buffer)?;
+        }
+        Ok(())
+    };
+    match entry.info.method {
+        0 => copy(&mut output)?,
+        8 => {
+            let mut decoder = DeflateDecoder::new(&mut output);
+            copy(&mut decoder)
+                .and_then(|_| decoder.finish().map(drop))
+                .map_err(|e| format_err!("{}{}", e, hint))?;
+        }
+        method => return Err(format_err!("unsupported compression method {}", method)),
+    }
+    if output.crc().sum() != entry.crc32 {
+        return Err(format_err!("CRC32 mismatch{}", hint));
+    }
+    output.flush()?;
+    Ok(())
+}
+
+/// 设置文件或目录的权限和修改时间
+fn set_attributes(file: &File, info: &EntryInfo) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Some(mode) = info.unix_mode() {
+            file.set_permissions(fs::Permissions::from_mode(mode & 0o7777))?;
+        }
+    }
+    if let Some(time) = modified(info) {
+        file.set_modified(time)?;
+    }
+    Ok(())
+}
+
+/// 删除 path 处已有的符号链接, 以免写入链接指向的文件
+fn remove_symlink(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &[u8], path: &Path) -> io::Result<()> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    remove_symlink(path)?;
+    std::os::unix::fs::symlink(OsStr::from_bytes(target), path)
+}
+
+/// 没有符号链接时与 p7zip 一样写成内容为目标路径的普通文件
+#[cfg(not(unix))]
+fn create_symlink(target: &[u8], path: &Path) -> io::Result<()> {
+    remove_symlink(path)?;
+    fs::write(path, target)
+}
+
+/// 条目解压后需要延后处理的部分
+enum Deferred {
+    Directory(PathBuf),
+    Symlink(PathBuf, Vec<u8>),
+}
+
+/// 解压普通文件, 目录和符号链接留到最后处理
+fn extract_entry(
+    reader: &mut MultiVolume,
+    entry: &ZipEntry,
+    keys: &Keys,
+    dir: &Path,
+) -> Result<Option<Deferred>, Error> {
+    let path = output_path(dir, &entry.info.name)
+        .ok_or_else(|| format_err!("refusing to extract outside of {}", dir.display()))?;
+    if entry.info.is_dir() {
+        fs::create_dir_all(&path)?;
+        return Ok(Some(Deferred::Directory(path)));
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let data = entry.read(reader, usize::MAX)?;
+    if entry.info.is_symlink() {
+        let mut target = vec![];
+        decode(entry, keys, &data, &mut target)?;
+        return Ok(Some(Deferred::Symlink(path, target)));
+    }
+
+    remove_symlink(&path)?;
+    let mut output = BufWriter::new(File::create(&path)?);
+    if let Err(e) = decode(entry, keys, &data, &mut output) {
+        // 不留下解密错误的文件
+        drop(output);
+        let _ = fs::remove_file(&path);
+        return Err(e);
+    }
+    let file = output.into_inner().map_err(|e| e.into_error())?;
+    set_attributes(&file, &entry.info)?;
+    Ok(None)
+}
+
+/// 用 keys 解压 path 中的所有条目到 dir, 每处理完一个条目以其结果调用 report
+///
+/// 目录的属性最后设置, 以免只读的目录无法写入; 符号链接最后创建, 以免之后的条目经由链接写到 dir 之外.
+/// 返回失败的条目数
+pub fn extract_all(
+    path: &str,
+    keys: &Keys,
+    dir: &Path,
+    mut report: impl FnMut(&ZipEntry, &Result<(), Error>),
+) -> Result<usize, Error> {
+    let entries = file::read_entries(path)?;
+    let mut reader = MultiVolume::open(path)?;
+    let mut failed = 0;
+    let mut report = |entry: &ZipEntry, result: Result<(), Error>| {
+        if result.is_err() {
+            failed += 1;
+        }
+        report(entry, &result);
+    };
+
+    let mut directories = vec![];
+    let mut symlinks = vec![];
+    for entry in &entries {
+        match extract_entry(&mut reader, entry, keys, dir) {
+            Ok(None) => report(entry, Ok(())),
+            Ok(Some(Deferred::Directory(path))) => directories.push((entry, path)),
+            Ok(Some(Deferred::Symlink(path, target))) => symlinks.push((entry, path, target)),
+            Err(e) => report(entry, Err(e)),
+        }
+    }
+
+    // 先设置子目录, 修改时间才不会被之后的操作改变
+    for (entry, path) in directories.into_iter().rev() {
+        let result = File::open(&path).and_then(|file| set_attributes(&file, &entry.info));
+        report(entry, result.map_err(Error::from));
+    }
+    for (entry, path, target) in symlinks {
+        report(entry, create_symlink(&target, &path).map_err(Error::from));
+    }
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract_all, modified, output_path};
+    use crate::file::{self, EntryInfo};
+    use crate::keys::Keys;
+    use crate::keystream_tab::KEYSTREAMTAB;
+    use crate::volume::MultiVolume;
+    use flate2::write::DeflateEncoder;
+    use flate2::{Compression, Crc};
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// 测试用的条目: 名字, 内容, 是否压缩, made_by, 外部属性, extra field, 密码
+    struct TestEntry {
+        name: &'static str,
+        data: &'static [u8],
+        deflate: bool,
+        made_by: u16,
+        external_attr: u32,
+        extra: Vec<u8>,
+        password: Option<&'static [u8]>,
+    }
+
+    /// 生成一个用 ZipCrypto 加密的 zip 文件
+    fn build_zip(entries: &[TestEntry]) -> Vec<u8> {
+        let u16le = |v: u16| v.to_le_bytes().to_vec();
+        let u32le = |v: u32| v.to_le_bytes().to_vec();
+        let mut zip = vec![];
+        let mut central = vec![];
+        for entry in entries {
+            let mut crc = Crc::new();
+            crc.update(entry.data);
+            let crc32 = crc.sum();
+            let mut data = if entry.deflate {
+                let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+                encoder.write_all(entry.data).unwrap();
+                encoder.finish().unwrap()
+            } else {
+                entry.data.to_vec()
+            };
+            if let Some(password) = entry.password {
+                let mut plain = (0..11u8).map(|i| i * 17).collect::<Vec<_>>();
+                plain.push((crc32 >> 24) as u8);
+                plain.extend(data);
+                let mut keys = Keys::from_password(password);
+                data = plain
+                    .iter()
+                    .map(|&p| {
+                        let c = p ^ KEYSTREAMTAB.get_byte(keys.get_z());
+                        keys.update(p);
+                        c
+                    })
+                    .collect();
+            }
+
+            // version(2) flags(2) method(2) time(2) date(2) crc32(4) compressed_size(4) size(4)
+            let mut fields = u16le(20);
+            fields.extend(u16le(entry.password.is_some() as u16));
+            fields.extend(u16le(if entry.deflate { 8 } else { 0 }));
+            fields.extend(u16le(0x1883)); // 03:04:06
+            fields.extend(u16le(0x5022)); // 2020-01-02
+            fields.extend(u32le(crc32));
+            fields.extend(u32le(data.len() as u32));
+            fields.extend(u32le(entry.data.len() as u32));
+            fields.extend(u16le(entry.name.len() as u16));
+            fields.extend(u16le(entry.extra.len() as u16));
+
+            central.extend(b"PK\x01\x02");
+            central.extend(u16le(entry.made_by));
+            central.extend(&fields);
+            // comment_len(2) disk_start(2) internal_attr(2) external_attr(4) header_offset(4)
+            central.extend(u16le(0));
+            central.extend(u16le(0));
+            central.extend(u16le(0));
+            central.extend(u32le(entry.external_attr));
+            central.extend(u32le(zip.len() as u32));
+            central.extend(entry.name.as_bytes());
+            central.extend(&entry.extra);
+
+            zip.extend(b"PK\x03\x04");
+            zip.extend(&fields);
+            zip.extend(entry.name.as_bytes());
+            zip.extend(&entry.extra);
+            zip.extend(data);
+        }
+
+        let cd_offset = zip.len() as u32;
+        zip.extend(&central);
+        zip.extend(b"PK\x05\x06");
+        zip.extend(u32le(0));
+        zip.extend(u16le(entries.len() as u16));
+        zip.extend(u16le(entries.len() as u16));
+        zip.extend(u32le(central.len() as u32));
+        zip.extend(u32le(cd_offset));
+        zip.extend(u16le(0));
+        zip
+    }
+
+    /// 把 build_zip 生成的 zip 文件在 at 处切为 PKZIP 式的两个分卷, 第一个分卷以分卷标记开头
+    fn split_zip(zip: &[u8], at: usize) -> (Vec<u8>, Vec<u8>) {
+        let u16at = |data: &[u8], i: usize| u16::from_le_bytes([data[i], data[i + 1]]) as usize;
+        let u32at = |data: &[u8], i: usize| {
+            u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize
+        };
+        let eocd = zip.len() - 22;
+        let cd_offset = u32at(zip, eocd + 16);
+        assert!(at <= cd_offset);
+
+        // central directory 中的偏移相对于 header 所在的分卷, 第一个分卷要算上标记
+        let mut central = zip[cd_offset..eocd].to_vec();
+        let mut i = 0;
+        while i < central.len() {
+            let offset = u32at(&central, i + 42);
+            let (disk, offset) = if offset < at {
+                (0u16, offset + 4)
+            } else {
+                (1, offset - at)
+            };
+            central[i + 34..i + 36].copy_from_slice(&disk.to_le_bytes());
+            central[i + 42..i + 46].copy_from_slice(&(offset as u32).to_le_bytes());
+            i += 46 + u16at(&central, i + 28) + u16at(&central, i + 30) + u16at(&central, i + 32);
+        }
+
+        let mut first = b"PK\x07\x08".to_vec();
+        first.extend(&zip[..at]);
+        let mut last = zip[at..cd_offset].to_vec();
+        let cd_offset = last.len() as u32;
+        last.extend(&central);
+        // EOCD: 当前分卷和 central directory 所在的分卷都是 1
+        last.extend(&zip[eocd..eocd + 4]);
+        last.extend(&[1, 0, 1, 0]);
+        last.extend(&zip[eocd + 8..eocd + 16]);
+        last.extend(&cd_offset.to_le_bytes());
+        last.extend(&zip[eocd + 20..]);
+        (first, last)
+    }
+
+    #[test]
+    fn safe_paths() {
+        let dir = Path::new("out");
+        assert_eq!(Some(dir.join("a").join("b")), output_path(dir, "a/b"));
+        assert_eq!(Some(dir.join("a").join("b")), output_path(dir, "/a/./b"));
+        assert_eq!(Some(dir.join("a").join("b")), output_path(dir, "a\\b"));
+        assert_eq!(None, output_path(dir, "a/../../b"));
+        assert_eq!(None, output_path(dir, "./"));
+    }
+
+    #[test]
+    fn timestamps() {
+        let mut info = EntryInfo {
+            name: "file".into(),
+            flags: 0,
+            method: 0,
+            mod_time: 0,
+            mod_date: 0,
+            made_by: 0,
+            external_attr: 0,
+            extra: vec![0x55, 0x54, 5, 0, 1, 0x00, 0x2f, 0x68, 0x59],
+        };
+        let expected = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        assert_eq!(Some(expected), modified(&info));
+
+        // 无效的 MS-DOS 日期
+        info.extra.clear();
+        assert_eq!(None, modified(&info));
+        info.mod_date = 0x5022;
+        assert!(modified(&info).is_some());
+    }
+
+    #[test]
+    fn extract() {
+        let dir =
+            std::env::temp_dir().join(format!("rbkcrack_extract_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let timestamp = vec![0x55, 0x54, 5, 0, 1, 0x00, 0x2f, 0x68, 0x59];
+        let entry = |name, data, external_attr: u32, password| TestEntry {
+            name,
+            data,
+            deflate: false,
+            made_by: 0x031e,
+            external_attr: external_attr << 16,
+            extra: vec![],
+            password,
+        };
+        let password: Option<&[u8]> = Some(b"secret");
+        let entries = vec![
+            entry("sub/", b"", 0o040_750, None),
+            TestEntry {
+                deflate: true,
+                extra: timestamp,
+                ..entry("sub/a.txt", b"hello hello hello\n", 0o100_644, password)
+            },
+            entry("empty", b"", 0o100_600, password),
+            entry("run.sh", b"#!/bin/sh\n", 0o100_755, password),
+            entry("link", b"sub/a.txt", 0o120_777, password),
+            TestEntry {
+                made_by: 0x0014,
+                external_attr: 0,
+                ..entry("plain.txt", b"not encrypted", 0, None)
+            },
+            entry("../evil", b"evil", 0o100_644, password),
+            entry("other", b"another password", 0o100_644, Some(b"other")),
+        ];
+        let zip = dir.join("test.zip");
+        fs::write(&zip, build_zip(&entries)).unwrap();
+
+        let out = dir.join("out");
+        let mut failures = vec![];
+        let failed = extract_all(
+            zip.to_str().unwrap(),
+            &Keys::from_password(b"secret"),
+            &out,
+            |entry, result| {
+                if result.is_err() {
+                    failures.push(entry.info.name.clone());
+                }
+            },
+        )
+        .unwrap();
+        assert_eq!(2, failed);
+        assert_eq!(vec!["../evil", "other"], failures);
+
+        assert_eq!(
+            b"hello hello hello\n".to_vec(),
+            fs::read(out.join("sub/a.txt")).unwrap()
+        );
+        assert_eq!(b"".to_vec(), fs::read(out.join("empty")).unwrap());
+        assert_eq!(
+            b"not encrypted".to_vec(),
+            fs::read(out.join("plain.txt")).unwrap()
+        );
+        assert!(!dir.join("evil").exists());
+        assert!(!out.join("other").exists());
+        let modified = fs::metadata(out.join("sub/a.txt"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        assert_eq!(UNIX_EPOCH + Duration::from_secs(1_500_000_000), modified);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode =
+                |path: &str| fs::metadata(out.join(path)).unwrap().permissions().mode() & 0o7777;
+            assert_eq!(0o755, mode("run.sh"));
+            assert_eq!(0o600, mode("empty"));
+            assert_eq!(0o750, mode("sub"));
+            assert_eq!(
+                Path::new("sub/a.txt"),
+                fs::read_link(out.join("link")).unwrap()
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn split_archive() {
+        let dir = std::env::temp_dir().join(format!("rbkcrack_split_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let entry = |name, data, password| TestEntry {
+            name,
+            data,
+            deflate: false,
+            made_by: 0x031e,
+            external_attr: 0o100_644 << 16,
+            extra: vec![],
+            password,
+        };
+        let plain_entries = [entry("a.txt", b"split across two volumes", None)];
+        let cipher_entries = [
+            entry("a.txt", b"split across two volumes", Some(b"secret")),
+            entry("b.txt", b"only in the last volume", Some(b"secret")),
+        ];
+
+        let plain = dir.join("plain.zip");
+        fs::write(&plain, build_zip(&plain_entries)).unwrap();
+        // 在第一个条目的数据中间切开
+        let (first, last) = split_zip(&build_zip(&cipher_entries), 50);
+        let cipher = dir.join("cipher.zip");
+        fs::write(dir.join("cipher.z01"), first).unwrap();
+        fs::write(&cipher, last).unwrap();
+
+        let (plain_data, cipher_data) =
+            file::auto_load_file(plain.to_str().unwrap(), cipher.to_str().unwrap()).unwrap();
+        assert_eq!(b"split across two volumes", &*plain_data);
+        assert_eq!(12 + plain_data.len(), cipher_data.len());
+
+        // 中央目录缺失时也要跳过分卷标记
+        let mut reader = MultiVolume::open(cipher.to_str().unwrap()).unwrap();
+        let scanned = file::scan_local_headers(&mut reader).unwrap();
+        assert_eq!(
+            vec!["a.txt", "b.txt"],
+            scanned
+                .iter()
+                .map(|e| e.info.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        drop(reader);
+
+        let out = dir.join("out");
+        let failed = extract_all(
+            cipher.to_str().unwrap(),
+            &Keys::from_password(b"secret"),
+            &out,
+            |_, _| (),
+        )
+        .unwrap();
+        assert_eq!(0, failed);
+        assert_eq!(
+            b"split across two volumes".to_vec(),
+            fs::read(out.join("a.txt")).unwrap()
+        );
+        assert_eq!(
+            b"only in the last volume".to_vec(),
+            fs::read(out.join("b.txt")).unwrap()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,84 +1,861 @@
+use crate::plain;
+use crate::volume::{self, MultiVolume};
 use failure::{format_err, Error};
-use log::debug;
-use podio::ReadPodExt;
+use log::{debug, info, warn};
+use memmap::{Mmap, MmapOptions};
+use podio::{LittleEndian, ReadPodExt};
 use std::collections::HashMap;
-use std::fs::{metadata, File};
+use std::fmt;
+use std::fs::File;
 use std::io::prelude::Seek;
-use std::io::{BufWriter, SeekFrom, Write};
-use zip::ZipArchive;
+use std::io::{BufWriter, Cursor, SeekFrom, Write};
+use std::ops::Deref;
+use std::sync::Arc;
 
-/// 自动根据 CRC32 值寻找匹配的文件
-pub fn auto_load_file(plain_zip: &str, cipher_zip: &str) -> Result<(Vec<u8>, Vec<u8>), Error> {
-    println!("Searching automatically...");
-    let mut plain_zip = ZipArchive::new(File::open(plain_zip)?)?;
-    let mut cipher_zip = ZipArchive::new(File::open(cipher_zip)?)?;
-
-    // 建立 crc32 - index 的索引
-    let map = (0..plain_zip.len())
-        .map(|i| {
-            let file = plain_zip.by_index(i).unwrap();
-            (file.crc32(), i)
+const LOCAL_SIGNATURE: [u8; 4] = *b"PK\x03\x04";
+const CENTRAL_SIGNATURE: [u8; 4] = *b"PK\x01\x02";
+const DESCRIPTOR_SIGNATURE: [u8; 4] = *b"PK\x07\x08";
+
+/// 读入的密文/明文
+///
+/// 文件通过内存映射读取, 切片时不会复制数据; 标准输入等无法映射的数据则直接保存
+#[derive(Clone)]
+pub enum Bytes {
+    Mapped {
+        map: Arc<Mmap>,
+        start: usize,
+        end: usize,
+    },
+    Owned(Arc<Vec<u8>>),
+}
+
+impl Bytes {
+    /// 映射文件中 [start, start + len) 的部分
+    pub fn map(file: &File, start: u64, len: usize) -> Result<Bytes, Error> {
+        // 长度为 0 的映射会失败
+        if len == 0 {
+            return Ok(Bytes::from(vec![]));
+        }
+        // 映射的起点需要按页对齐, 这里直接从文件开头映射
+        let map = unsafe { MmapOptions::new().len(start as usize + len).map(file)? };
+        Ok(Bytes::Mapped {
+            map: Arc::new(map),
+            start: start as usize,
+            end: start as usize + len,
         })
+    }
+
+    /// 返回前 len 个字节, 不复制数据
+    pub fn prefix(&self, len: usize) -> Bytes {
+        match self {
+            Bytes::Mapped { map, start, end } => Bytes::Mapped {
+                map: Arc::clone(map),
+                start: *start,
+                end: *end.min(&(start + len)),
+            },
+            Bytes::Owned(data) if len < data.len() => Bytes::from(data[..len].to_vec()),
+            Bytes::Owned(data) => Bytes::Owned(Arc::clone(data)),
+        }
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Bytes::Mapped { map, start, end } => &map[*start..*end],
+            Bytes::Owned(data) => data,
+        }
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(data: Vec<u8>) -> Bytes {
+        Bytes::Owned(Arc::new(data))
+    }
+}
+
+impl fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Bytes::Mapped { .. } => write!(f, "Mapped({} bytes)", self.len()),
+            Bytes::Owned(_) => write!(f, "Owned({} bytes)", self.len()),
+        }
+    }
+}
+
+/// zip 条目使用的加密方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encryption {
+    /// 未加密
+    None,
+    /// 传统 PKWARE 加密 (ZipCrypto), 唯一可以攻击的加密方式
+    ZipCrypto,
+    /// WinZip AES (method 99), 参数为密钥长度
+    Aes(u16),
+    /// PKWARE strong encryption, 参数为算法 ID
+    Strong(u16),
+}
+
+impl fmt::Display for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Encryption::None => write!(f, "no encryption"),
+            Encryption::ZipCrypto => write!(f, "ZipCrypto"),
+            Encryption::Aes(0) => write!(f, "WinZip AES"),
+            Encryption::Aes(bits) => write!(f, "WinZip AES-{}", bits),
+            Encryption::Strong(alg_id) => {
+                let alg = match alg_id {
+                    0x6601 => "DES",
+                    0x6602 | 0x6702 => "RC2",
+                    0x6603 => "3DES-168",
+                    0x6609 => "3DES-112",
+                    0x660e => "AES-128",
+                    0x660f => "AES-192",
+                    0x6610 => "AES-256",
+                    0x6720 => "Blowfish",
+                    0x6721 => "Twofish",
+                    0x6801 => "RC4",
+                    _ => "unknown algorithm",
+                };
+                write!(f, "PKWARE strong encryption ({})", alg)
+            }
+        }
+    }
+}
+
+/// central directory 中记录的条目信息
+#[derive(Debug, Clone)]
+pub struct EntryInfo {
+    pub name: String,
+    pub flags: u16,
+    pub method: u16,
+    /// MS-DOS 格式的修改时间
+    pub mod_time: u16,
+    /// MS-DOS 格式的修改日期
+    pub mod_date: u16,
+    /// 创建条目的系统 (高字节) 及版本, local file header 中没有, 为 0
+    pub made_by: u16,
+    /// 外部属性, 低字节为 MS-DOS 属性, 由 Unix 创建时高 16 位为 mode
+    pub external_attr: u32,
+    pub extra: Vec<u8>,
+}
+
+impl EntryInfo {
+    const FLAG_ENCRYPTED: u16 = 1;
+    const FLAG_STRONG_ENCRYPTION: u16 = 1 << 6;
+    const METHOD_AES: u16 = 99;
+    const EXTRA_AES: u16 = 0x9901;
+    const EXTRA_STRONG_ENCRYPTION: u16 = 0x0017;
+    const HOST_UNIX: u16 = 3;
+    const DOS_DIRECTORY: u32 = 0x10;
+    const S_IFMT: u32 = 0o170_000;
+    const S_IFDIR: u32 = 0o040_000;
+    const S_IFLNK: u32 = 0o120_000;
+
+    /// 由 Unix 创建的条目的 mode
+    pub fn unix_mode(&self) -> Option<u32> {
+        Some(self.external_attr >> 16)
+            .filter(|&mode| self.made_by >> 8 == Self::HOST_UNIX && mode != 0)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+            || self.external_attr & Self::DOS_DIRECTORY != 0
+            || self
+                .unix_mode()
+                .is_some_and(|mode| mode & Self::S_IFMT == Self::S_IFDIR)
+    }
+
+    /// 符号链接的数据为链接的目标
+    pub fn is_symlink(&self) -> bool {
+        self.unix_mode()
+            .is_some_and(|mode| mode & Self::S_IFMT == Self::S_IFLNK)
+    }
+
+    /// 根据 flags, 压缩方法和 extra field 判断加密方式
+    pub fn encryption(&self) -> Encryption {
+        if self.flags & Self::FLAG_ENCRYPTED == 0 {
+            Encryption::None
+        } else if self.method == Self::METHOD_AES {
+            // AES extra field: version(2) vendor(2) strength(1) method(2)
+            let bits = match find_extra_field(&self.extra, Self::EXTRA_AES).map(|f| f.get(4)) {
+                Some(Some(1)) => 128,
+                Some(Some(2)) => 192,
+                Some(Some(3)) => 256,
+                _ => 0,
+            };
+            Encryption::Aes(bits)
+        } else if self.flags & Self::FLAG_STRONG_ENCRYPTION != 0 {
+            // strong encryption header: format(2) alg_id(2) bitlen(2) flags(2)
+            let alg_id = find_extra_field(&self.extra, Self::EXTRA_STRONG_ENCRYPTION)
+                .filter(|f| f.len() >= 4)
+                .map_or(0xffff, |f| u16::from(f[2]) | u16::from(f[3]) << 8);
+            Encryption::Strong(alg_id)
+        } else {
+            Encryption::ZipCrypto
+        }
+    }
+}
+
+/// 在 extra field 中查找指定 ID 的字段, 返回其数据部分
+pub(crate) fn find_extra_field(mut extra: &[u8], id: u16) -> Option<&[u8]> {
+    while extra.len() >= 4 {
+        let field_id = u16::from(extra[0]) | u16::from(extra[1]) << 8;
+        let len = (u16::from(extra[2]) | u16::from(extra[3]) << 8) as usize;
+        let data = extra.get(4..4 + len)?;
+        if field_id == id {
+            return Some(data);
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+/// zip 条目的元数据及其数据在文件中的位置
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    pub info: EntryInfo,
+    pub crc32: u32,
+    /// 数据 (包括 12 字节的加密头) 的起始位置
+    pub data_start: u64,
+    pub compressed_size: u64,
+}
+
+impl ZipEntry {
+    const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+    const EXTRA_ZIP64: u16 = 0x0001;
+
+    /// 检查条目是否使用 ZipCrypto 以外的加密方式
+    fn check_encryption(&self) -> Result<(), Error> {
+        match self.info.encryption() {
+            Encryption::None | Encryption::ZipCrypto => Ok(()),
+            encryption => Err(format_err!(
+                "{} is encrypted with {}, only ZipCrypto is supported",
+                self.info.name,
+                encryption
+            )),
+        }
+    }
+
+    /// 加密头最后一个字节的期望值, 可以用来快速排除错误的密码
+    ///
+    /// 使用 data descriptor 时写加密头时还不知道 CRC32, 改用修改时间的高字节
+    pub fn check_byte(&self) -> u8 {
+        if self.info.flags & Self::FLAG_DATA_DESCRIPTOR != 0 {
+            (self.info.mod_time >> 8) as u8
+        } else {
+            (self.crc32 >> 24) as u8
+        }
+    }
+
+    /// 读取条目数据的前 size 个字节
+    pub fn read(&self, reader: &mut MultiVolume, size: usize) -> Result<Bytes, Error> {
+        // 记录的大小不可信, 不能超出文件的实际长度
+        let available = reader
+            .len()
+            .checked_sub(self.data_start)
+            .ok_or_else(|| format_err!("data of {} is out of the file", self.info.name))?;
+        let size = size.min(self.compressed_size.min(available) as usize);
+        if let Some((file, start)) = reader.volume_of(self.data_start, size as u64) {
+            return Bytes::map(file, start, size);
+        }
+        // 跨越多个分卷的数据只能复制
+        reader.seek(SeekFrom::Start(self.data_start))?;
+        Ok(Bytes::from(reader.read_exact(size)?))
+    }
+}
+
+/// 读取 zip 文件 (或分卷组) 中的所有条目, 若 central directory 损坏则扫描 local file header
+pub fn read_entries(path: &str) -> Result<Vec<ZipEntry>, Error> {
+    let mut reader = MultiVolume::open(path)?;
+    let volume_starts = reader.volume_starts().to_vec();
+    match read_central_directory(&mut reader, &volume_starts) {
+        Ok(entries) => Ok(entries),
+        Err(e) => {
+            warn!("could not read central directory of {}: {}", path, e);
+            info!("scanning local file headers");
+            let entries = scan_local_headers(&mut reader)?;
+            if entries.is_empty() {
+                return Err(format_err!("could not find any entry in {}", path));
+            }
+            Ok(entries)
+        }
+    }
+}
+
+/// 读取 central directory, 按顺序返回所有条目
+///
+/// 分卷压缩包中的偏移是相对于各自分卷的, 需要 volume_starts 来换算成流中的位置
+pub fn read_central_directory<R: ReadPodExt + Seek>(
+    reader: &mut R,
+    volume_starts: &[u64],
+) -> Result<Vec<ZipEntry>, Error> {
+    const EOCD_SIGNATURE: [u8; 4] = *b"PK\x05\x06";
+    const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+    const ZIP64_EOCD_SIGNATURE: u32 = 0x0606_4b50;
+
+    // end of central directory 位于文件末尾, 之后最多跟着 65535 字节的注释
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    let tail_len = file_len.min(22 + 0xffff);
+    reader.seek(SeekFrom::Start(file_len - tail_len))?;
+    let tail = reader.read_exact(tail_len as usize)?;
+    let eocd_pos = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| tail[i..i + 4] == EOCD_SIGNATURE)
+        .ok_or_else(|| format_err!("could not find end of central directory"))?;
+
+    // EOCD: signature(4) disk(2) cd_disk(2) disk_entries(2) entries(2) cd_size(4) cd_offset(4)
+    let mut eocd = Cursor::new(&tail[eocd_pos + 4..]);
+    let mut disk = u32::from(eocd.read_u16::<LittleEndian>()?);
+    let mut cd_disk = u32::from(eocd.read_u16::<LittleEndian>()?);
+    eocd.read_u16::<LittleEndian>()?;
+    let mut entries = u64::from(eocd.read_u16::<LittleEndian>()?);
+    let mut cd_size = u64::from(eocd.read_u32::<LittleEndian>()?);
+    let mut cd_offset = u64::from(eocd.read_u32::<LittleEndian>()?);
+    let mut cd_end = file_len - tail_len + eocd_pos as u64;
+
+    // ZIP64 end of central directory locator 紧挨在 EOCD 之前
+    if cd_end >= 20 {
+        reader.seek(SeekFrom::Start(cd_end - 20))?;
+        if reader.read_u32::<LittleEndian>()? == ZIP64_LOCATOR_SIGNATURE {
+            let zip64_eocd_disk = reader.read_u32::<LittleEndian>()? as usize;
+            let zip64_eocd = reader.read_u64::<LittleEndian>()?;
+            // 同 EOCD 一样, 先按相对位置定位, 以兼容自解压等带前缀的文件
+            cd_end = cd_end.saturating_sub(20 + 56);
+            reader.seek(SeekFrom::Start(cd_end))?;
+            if reader.read_u32::<LittleEndian>()? != ZIP64_EOCD_SIGNATURE {
+                cd_end = volume_starts
+                    .get(zip64_eocd_disk)
+                    .cloned()
+                    .unwrap_or(0)
+                    .checked_add(zip64_eocd)
+                    .ok_or_else(|| format_err!("invalid zip64 end of central directory"))?;
+                reader.seek(SeekFrom::Start(cd_end))?;
+                if reader.read_u32::<LittleEndian>()? != ZIP64_EOCD_SIGNATURE {
+                    return Err(format_err!("invalid zip64 end of central directory"));
+                }
+            }
+            reader.seek(SeekFrom::Current(12))?;
+            disk = reader.read_u32::<LittleEndian>()?;
+            cd_disk = reader.read_u32::<LittleEndian>()?;
+            reader.read_u64::<LittleEndian>()?;
+            entries = reader.read_u64::<LittleEndian>()?;
+            cd_size = reader.read_u64::<LittleEndian>()?;
+            cd_offset = reader.read_u64::<LittleEndian>()?;
+        }
+    }
+
+    // 计算各分卷的起始位置
+    // 单个文件时为 central directory 实际位置与记录位置之差 (如自解压文件的前缀)
+    // 第一个分卷开头的分卷标记已经算在记录的偏移中, 不需要另外跳过
+    let base = if disk == 0 {
+        let archive_offset = cd_end
+            .checked_sub(cd_size)
+            .and_then(|start| start.checked_sub(cd_offset))
+            .ok_or_else(|| format_err!("invalid central directory offset"))?;
+        vec![archive_offset]
+    } else if disk as usize + 1 == volume_starts.len() {
+        volume_starts.to_vec()
+    } else {
+        return Err(format_err!(
+            "the archive has {} volumes but only {} are found",
+            disk + 1,
+            volume_starts.len()
+        ));
+    };
+    // 偏移都来自文件本身, 相加时需要检查溢出
+    let locate = |disk: u32, offset: u64| {
+        base.get(disk as usize)
+            .ok_or_else(|| format_err!("invalid disk number {}", disk))?
+            .checked_add(offset)
+            .ok_or_else(|| format_err!("invalid offset {}", offset))
+    };
+
+    reader.seek(SeekFrom::Start(locate(cd_disk, cd_offset)?))?;
+    let mut headers = Vec::new();
+    for _ in 0..entries {
+        // central file header: signature(4) version_made_by(2) version_needed(2) flags(2) method(2)
+        // time(2) date(2) crc32(4) compressed_size(4) size(4) name_len(2) extra_len(2)
+        // comment_len(2) disk_start(2) internal_attr(2) external_attr(4) header_offset(4)
+        if reader.read_exact(4)? != CENTRAL_SIGNATURE {
+            return Err(format_err!("invalid central directory header"));
+        }
+        let made_by = reader.read_u16::<LittleEndian>()?;
+        reader.seek(SeekFrom::Current(2))?;
+        let flags = reader.read_u16::<LittleEndian>()?;
+        let method = reader.read_u16::<LittleEndian>()?;
+        let mod_time = reader.read_u16::<LittleEndian>()?;
+        let mod_date = reader.read_u16::<LittleEndian>()?;
+        let crc32 = reader.read_u32::<LittleEndian>()?;
+        let mut compressed_size = u64::from(reader.read_u32::<LittleEndian>()?);
+        let size = reader.read_u32::<LittleEndian>()?;
+        let name_len = reader.read_u16::<LittleEndian>()? as usize;
+        let extra_len = reader.read_u16::<LittleEndian>()? as usize;
+        let comment_len = i64::from(reader.read_u16::<LittleEndian>()?);
+        let mut disk_start = u32::from(reader.read_u16::<LittleEndian>()?);
+        reader.seek(SeekFrom::Current(2))?;
+        let external_attr = reader.read_u32::<LittleEndian>()?;
+        let mut header_offset = u64::from(reader.read_u32::<LittleEndian>()?);
+        let name = String::from_utf8_lossy(&reader.read_exact(name_len)?).into_owned();
+        let extra = reader.read_exact(extra_len)?;
+        reader.seek(SeekFrom::Current(comment_len))?;
+
+        // ZIP64 extra field 中只包含取值为 0xffffffff 的字段
+        if let Some(field) = find_extra_field(&extra, ZipEntry::EXTRA_ZIP64) {
+            let mut field = Cursor::new(field);
+            if size == 0xffff_ffff {
+                field.read_u64::<LittleEndian>()?;
+            }
+            if compressed_size == 0xffff_ffff {
+                compressed_size = field.read_u64::<LittleEndian>()?;
+            }
+            if header_offset == 0xffff_ffff {
+                header_offset = field.read_u64::<LittleEndian>()?;
+            }
+            if disk_start == 0xffff {
+                disk_start = field.read_u32::<LittleEndian>()?;
+            }
+        }
+
+        let info = EntryInfo {
+            name,
+            flags,
+            method,
+            mod_time,
+            mod_date,
+            made_by,
+            external_attr,
+            extra,
+        };
+        headers.push((
+            info,
+            crc32,
+            compressed_size,
+            locate(disk_start, header_offset)?,
+        ));
+    }
+
+    // 数据紧跟在 local file header 之后, 而 local header 中 name 和 extra field 的长度可能与 central directory 中的不同
+    headers
+        .into_iter()
+        .map(|(info, crc32, compressed_size, header_start)| {
+            reader.seek(SeekFrom::Start(header_start))?;
+            if reader.read_exact(4)? != LOCAL_SIGNATURE {
+                return Err(format_err!("invalid local file header of {}", info.name));
+            }
+            reader.seek(SeekFrom::Current(22))?;
+            let name_len = u64::from(reader.read_u16::<LittleEndian>()?);
+            let extra_len = u64::from(reader.read_u16::<LittleEndian>()?);
+            Ok(ZipEntry {
+                info,
+                crc32,
+                data_start: header_start + 30 + name_len + extra_len,
+                compressed_size,
+            })
+        })
+        .collect()
+}
+
+/// 文件中 local file header, data descriptor 和 central directory header 签名的位置
+#[derive(Default)]
+struct Signatures {
+    local: Vec<u64>,
+    descriptor: Vec<u64>,
+    central: Vec<u64>,
+}
+
+impl Signatures {
+    /// 只扫描一遍文件, 之后的查找都使用记录的位置
+    fn scan<R: ReadPodExt + Seek>(reader: &mut R, file_len: u64) -> Result<Signatures, Error> {
+        const CHUNK_SIZE: u64 = 1 << 16;
+
+        let mut signatures = Signatures::default();
+        let mut pos = 0;
+        while pos + 4 <= file_len {
+            let len = CHUNK_SIZE.min(file_len - pos);
+            reader.seek(SeekFrom::Start(pos))?;
+            let chunk = reader.read_exact(len as usize)?;
+            for (i, window) in chunk.windows(4).enumerate() {
+                let positions = if window == LOCAL_SIGNATURE {
+                    &mut signatures.local
+                } else if window == DESCRIPTOR_SIGNATURE {
+                    &mut signatures.descriptor
+                } else if window == CENTRAL_SIGNATURE {
+                    &mut signatures.central
+                } else {
+                    continue;
+                };
+                positions.push(pos + i as u64);
+            }
+            // 保留 3 个字节, 以免漏掉跨块的签名
+            pos += len - 3;
+        }
+        Ok(signatures)
+    }
+
+    /// positions 中不小于 pos 的部分
+    fn from(positions: &[u64], pos: u64) -> &[u64] {
+        &positions[positions.partition_point(|&p| p < pos)..]
+    }
+}
+
+/// 依次扫描所有的 local file header, 用于 central directory 损坏或缺失的情况
+pub fn scan_local_headers<R: ReadPodExt + Seek>(reader: &mut R) -> Result<Vec<ZipEntry>, Error> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    let signatures = Signatures::scan(reader, file_len)?;
+    let mut entries = Vec::new();
+    // 分卷标记的签名与 data descriptor 相同, 直接跳过
+    let mut pos = volume::split_marker_len(reader)?;
+
+    while let Some(&header_start) = Signatures::from(&signatures.local, pos).first() {
+        match read_local_header(reader, header_start, file_len, &signatures)? {
+            Some((entry, complete)) => {
+                debug!(
+                    "found {} at {}, size {}",
+                    entry.info.name, entry.data_start, entry.compressed_size
+                );
+                // 大小未知时数据中可能还藏着下一个 header, 只跳过 header 本身
+                pos = if complete {
+                    entry.data_start + entry.compressed_size
+                } else {
+                    entry.data_start
+                };
+                entries.push(entry);
+            }
+            // 压缩数据中偶然出现的签名
+            None => pos = header_start + 1,
+        }
+    }
+    Ok(entries)
+}
+
+/// 解析位于 header_start 的 local file header
+/// 返回条目以及其数据是否完整, 若不像一个合法的 header 则返回 None
+fn read_local_header<R: ReadPodExt + Seek>(
+    reader: &mut R,
+    header_start: u64,
+    file_len: u64,
+    signatures: &Signatures,
+) -> Result<Option<(ZipEntry, bool)>, Error> {
+    const HEADER_SIZE: u64 = 30;
+
+    if header_start + HEADER_SIZE > file_len {
+        return Ok(None);
+    }
+    reader.seek(SeekFrom::Start(header_start + 4))?;
+    let version = reader.read_u16::<LittleEndian>()?;
+    let flags = reader.read_u16::<LittleEndian>()?;
+    let method = reader.read_u16::<LittleEndian>()?;
+    let mod_time = reader.read_u16::<LittleEndian>()?;
+    let mod_date = reader.read_u16::<LittleEndian>()?;
+    let mut crc32 = reader.read_u32::<LittleEndian>()?;
+    let mut compressed_size = u64::from(reader.read_u32::<LittleEndian>()?);
+    reader.read_u32::<LittleEndian>()?;
+    let name_len = reader.read_u16::<LittleEndian>()?;
+    let extra_len = reader.read_u16::<LittleEndian>()?;
+
+    // 过滤明显不合理的 header
+    let data_start = header_start + HEADER_SIZE + u64::from(name_len) + u64::from(extra_len);
+    let known_method = matches!(method, 0..=10 | 12 | 14 | 18 | 19 | 93 | 95..=99);
+    if version & 0xff > 63 || !known_method || name_len == 0 || data_start > file_len {
+        return Ok(None);
+    }
+
+    let name = String::from_utf8_lossy(&reader.read_exact(name_len as usize)?).into_owned();
+    let extra = reader.read_exact(extra_len as usize)?;
+
+    if compressed_size == 0xffff_ffff {
+        // ZIP64 extra field: size(8) compressed_size(8)
+        if let Some(field) = find_extra_field(&extra, ZipEntry::EXTRA_ZIP64) {
+            if field.len() >= 16 {
+                compressed_size = Cursor::new(&field[8..]).read_u64::<LittleEndian>()?;
+            }
+        }
+    }
+
+    let mut complete = compressed_size <= file_len - data_start;
+    if flags & ZipEntry::FLAG_DATA_DESCRIPTOR != 0 {
+        // CRC32 和大小都记录在数据之后的 data descriptor 中
+        // descriptor: [signature(4)] crc32(4) compressed_size(4 or 8) size(4 or 8)
+        complete = false;
+        for &descriptor in Signatures::from(&signatures.descriptor, data_start) {
+            // 离文件末尾太近, 放不下完整的 descriptor
+            if descriptor + 16 > file_len {
+                break;
+            }
+            let size = descriptor - data_start;
+            reader.seek(SeekFrom::Start(descriptor + 4))?;
+            let descriptor_crc32 = reader.read_u32::<LittleEndian>()?;
+            let size32 = reader.read_u32::<LittleEndian>()?;
+            let size64 = u64::from(size32) | u64::from(reader.read_u32::<LittleEndian>()?) << 32;
+            if u64::from(size32) == size || size64 == size {
+                crc32 = descriptor_crc32;
+                compressed_size = size;
+                complete = true;
+                break;
+            }
+        }
+
+        if !complete {
+            // 没有带签名的 descriptor, 数据至多延续到下一个 header
+            let next_local = Signatures::from(&signatures.local, data_start).first();
+            let next_central = Signatures::from(&signatures.central, data_start).first();
+            let end = next_local
+                .into_iter()
+                .chain(next_central)
+                .min()
+                .copied()
+                .unwrap_or(file_len);
+            compressed_size = end - data_start;
+            if end != file_len && compressed_size >= 12 {
+                reader.seek(SeekFrom::Start(end - 8))?;
+                if u64::from(reader.read_u32::<LittleEndian>()?) == compressed_size - 12 {
+                    reader.seek(SeekFrom::Start(end - 12))?;
+                    crc32 = reader.read_u32::<LittleEndian>()?;
+                    compressed_size -= 12;
+                    complete = true;
+                }
+            }
+        }
+    } else if !complete {
+        // 文件被截断
+        compressed_size = file_len - data_start;
+    }
+
+    let entry = ZipEntry {
+        info: EntryInfo {
+            name,
+            flags,
+            method,
+            mod_time,
+            mod_date,
+            made_by: 0,
+            external_attr: 0,
+            extra,
+        },
+        crc32,
+        data_start,
+        compressed_size,
+    };
+    Ok(Some((entry, complete)))
+}
+
+/// 自动根据 CRC32 值寻找匹配的文件
+pub fn auto_load_file(plain_zip: &str, cipher_zip: &str) -> Result<(Bytes, Bytes), Error> {
+    info!("searching automatically");
+    let plain_entries = read_entries(plain_zip)?;
+    let cipher_entries = read_entries(cipher_zip)?;
+
+    // 建立 crc32 - entry 的索引
+    let map = plain_entries
+        .iter()
+        .map(|entry| (entry.crc32, entry))
         .collect::<HashMap<_, _>>();
 
     // 遍历 cipher_zip, 寻找 crc32 匹配的文件
-    for i in 0..cipher_zip.len() {
-        let file = cipher_zip.by_index(i).unwrap();
-
-        if let Some(index) = map.get(&file.crc32()) {
-            // 提前获取 data_start, compressed_size 以取悦 borrow checker
-            let (data_start, plain_size) = {
-                let file = plain_zip.by_index(*index).unwrap();
-                println!("Found plain: {}", file.name());
-                (file.data_start(), file.compressed_size())
-            };
-            let mut plain = plain_zip.into_inner();
-            plain.seek(SeekFrom::Start(data_start))?;
+    for cipher in &cipher_entries {
+        // 只有 ZipCrypto 加密的条目才能攻击
+        match cipher.info.encryption() {
+            Encryption::ZipCrypto => (),
+            encryption => {
+                info!("skipped cipher: {} ({})", cipher.info.name, encryption);
+                continue;
+            }
+        }
 
-            // 此处因为 file 定义在外层, 只能主动 drop 以通过 borrow check
-            let (data_start, cipher_size) = (file.data_start(), file.compressed_size());
-            println!("Found cipher: {}", file.name());
-            drop(file);
-            let mut cipher = cipher_zip.into_inner();
-            cipher.seek(SeekFrom::Start(data_start))?;
+        if let Some(plain) = map.get(&cipher.crc32) {
+            info!("found plain: {}", plain.info.name);
+            info!("found cipher: {}", cipher.info.name);
 
             return Ok((
-                plain.read_exact(plain_size as usize)?,
-                cipher.read_exact(cipher_size as usize)?,
+                plain.read(&mut MultiVolume::open(plain_zip)?, usize::MAX)?,
+                cipher.read(&mut MultiVolume::open(cipher_zip)?, usize::MAX)?,
             ));
         }
     }
     Err(format_err!("could not find matched files"))
 }
 
-/// 读取一个包含密文/明文的文件
-pub fn read_raw_file(path: &str, size: usize) -> Result<Vec<u8>, Error> {
-    let mut file = File::open(path)?;
-    let meta = metadata(path)?;
-    Ok(file.read_exact(size.min(meta.len() as usize))?)
+/// 读取一个包含密文/明文的文件, `-` 表示标准输入
+pub fn read_raw_file(path: &str, size: usize) -> Result<Bytes, Error> {
+    if path == "-" {
+        return Ok(plain::read_stdin()?.prefix(size));
+    }
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    Bytes::map(&file, 0, size.min(len as usize))
 }
 
 /// 读取一个包含密文/明文的 zip 文件的条目
-pub fn read_zip_entry(path: &str, entry_name: &str, size: usize) -> Result<Vec<u8>, Error> {
+pub fn read_zip_entry(path: &str, entry_name: &str, size: usize) -> Result<Bytes, Error> {
     debug!("loading {}", path);
-    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let entries = read_entries(path)?;
 
     debug!("searching {}", entry_name);
-    let zip_file = zip.by_name(entry_name)?;
-
-    let data_start = zip_file.data_start();
-    let real_size = zip_file.compressed_size() as usize;
-
-    drop(zip_file);
-
-    let mut reader = zip.into_inner();
-    reader.seek(SeekFrom::Start(data_start))?;
+    let entry = entries
+        .iter()
+        .find(|entry| entry.info.name == entry_name)
+        .ok_or_else(|| format_err!("could not find {} in {}", entry_name, path))?;
+    entry.check_encryption()?;
 
-    debug!("Found! size: {}", size);
+    debug!("Found! size: {}", entry.compressed_size);
 
-    Ok(reader.read_exact(size.min(real_size))?)
+    entry.read(&mut MultiVolume::open(path)?, size)
 }
 
 pub fn open_output(path: &str) -> Result<impl Write, Error> {
     Ok(BufWriter::new(File::create(path)?))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        read_central_directory, read_entries, scan_local_headers, Bytes, Encryption, EntryInfo,
+    };
+    use crate::keys::Keys;
+    use crate::volume::MultiVolume;
+    use podio::ReadPodExt;
+    use std::fs::File;
+    use std::io::Cursor;
+
+    #[test]
+    fn encryption() {
+        let mut info = EntryInfo {
+            name: "file".into(),
+            flags: 0,
+            method: 8,
+            mod_time: 0,
+            mod_date: 0,
+            made_by: 0,
+            external_attr: 0,
+            extra: vec![],
+        };
+        assert_eq!(Encryption::None, info.encryption());
+
+        info.flags = 1;
+        assert_eq!(Encryption::ZipCrypto, info.encryption());
+
+        info.flags = 1 | 1 << 6;
+        info.extra = vec![0x17, 0, 8, 0, 2, 0, 0x10, 0x66, 0, 1, 1, 0];
+        assert_eq!(Encryption::Strong(0x6610), info.encryption());
+
+        info.flags = 1;
+        info.method = 99;
+        info.extra = vec![0x01, 0x99, 7, 0, 2, 0, b'A', b'E', 3, 8, 0];
+        assert_eq!(Encryption::Aes(256), info.encryption());
+    }
+
+    #[test]
+    fn file_type() {
+        let mut info = EntryInfo {
+            name: "dir/".into(),
+            flags: 0,
+            method: 0,
+            mod_time: 0,
+            mod_date: 0,
+            made_by: 0x031e,
+            external_attr: 0o040_755 << 16,
+            extra: vec![],
+        };
+        assert!(info.is_dir());
+        assert_eq!(Some(0o040_755), info.unix_mode());
+
+        info.name = "link".into();
+        info.external_attr = 0o120_777 << 16;
+        assert!(!info.is_dir());
+        assert!(info.is_symlink());
+
+        // MS-DOS 创建的条目的高 16 位不是 mode
+        info.made_by = 0x0014;
+        assert_eq!(None, info.unix_mode());
+        assert!(!info.is_symlink());
+        info.external_attr = 0x10;
+        assert!(info.is_dir());
+    }
+
+    #[test]
+    fn read_entries_from_zip() {
+        let entries = read_entries("./example/cipher.zip").unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!("file", entries[0].info.name);
+        assert_eq!(0xd819_6681, entries[0].crc32);
+        assert_eq!(34, entries[0].data_start);
+        assert_eq!(906_285, entries[0].compressed_size);
+        assert_eq!(Encryption::ZipCrypto, entries[0].info.encryption());
+
+        // 用已知的密钥解密加密头, 最后一个字节应为校验字节
+        let mut header = entries[0]
+            .read(&mut MultiVolume::open("./example/cipher.zip").unwrap(), 12)
+            .unwrap()
+            .to_vec();
+        let mut keys = Keys::new();
+        keys.set_keys(0x8879_dfed, 0x1433_5b6b, 0x8dc5_8b53);
+        keys.decipher(&mut header);
+        assert_eq!(entries[0].check_byte(), header[11]);
+    }
+
+    #[test]
+    fn scan_truncated_zip() {
+        let central = read_entries("./example/cipher.zip").unwrap();
+
+        // 截断文件, 丢掉 central directory
+        let data = File::open("./example/cipher.zip")
+            .unwrap()
+            .read_exact(500_000)
+            .unwrap();
+        let scanned = scan_local_headers(&mut Cursor::new(data)).unwrap();
+
+        assert_eq!(1, scanned.len());
+        assert_eq!(central[0].info.name, scanned[0].info.name);
+        assert_eq!(central[0].crc32, scanned[0].crc32);
+        assert_eq!(central[0].data_start, scanned[0].data_start);
+        assert_eq!(500_000 - central[0].data_start, scanned[0].compressed_size);
+        assert_eq!(Encryption::ZipCrypto, scanned[0].info.encryption());
+    }
+
+    #[test]
+    fn scan_descriptor_at_end() {
+        // 使用 data descriptor 的条目, 文件在 descriptor 的签名之后被截断
+        let mut data = b"PK\x03\x04\x14\x00\x09\x00\x00\x00".to_vec();
+        data.extend(&[0; 16]);
+        data.extend(b"\x01\x00\x00\x00a");
+        data.extend(&[0x55; 20]);
+        data.extend(b"PK\x07\x08\x00\x00\x00\x00");
+        let scanned = scan_local_headers(&mut Cursor::new(data)).unwrap();
+
+        assert_eq!(1, scanned.len());
+        assert_eq!(31, scanned[0].data_start);
+        assert_eq!(28, scanned[0].compressed_size);
+    }
+
+    #[test]
+    fn overflowing_offset() {
+        // 1 字节前缀 + central directory + EOCD, header_offset 放在 ZIP64 extra field 中
+        let mut data = b"x".to_vec();
+        data.extend_from_slice(b"PK\x01\x02");
+        data.extend_from_slice(&[0; 24]);
+        data.extend_from_slice(&[1, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0xff; 4]);
+        data.push(b'a');
+        data.extend_from_slice(&[1, 0, 8, 0]);
+        data.extend_from_slice(&[0xff; 8]);
+        data.extend_from_slice(b"PK\x05\x06");
+        data.extend_from_slice(&[0, 0, 0, 0, 1, 0, 1, 0, 59, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        assert!(read_central_directory(&mut Cursor::new(data), &[0]).is_err());
+    }
+
+    #[test]
+    fn map_file() {
+        let data = std::fs::read("./example/cipher.zip").unwrap();
+        let file = File::open("./example/cipher.zip").unwrap();
+
+        let bytes = Bytes::map(&file, 34, 1000).unwrap();
+        assert_eq!(&data[34..1034], &*bytes);
+        assert_eq!(&data[34..134], &*bytes.prefix(100));
+        assert_eq!(1000, bytes.prefix(2000).len());
+        assert!(Bytes::map(&file, 0, 0).unwrap().is_empty());
+    }
+}
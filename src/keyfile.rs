@@ -0,0 +1,268 @@
+//! 读写其他工具格式的密钥
+//!
+//! - bkcrack: `8879dfed 14335b6b 8dc58b53`, 可带 `Keys:` 前缀
+//! - pkcrack: `key0=8879dfed, key1=14335b6b, key2=8dc58b53`
+//! - p7zip (Aloxaf/p7zip): `[8879dfed_14335b6b_8dc58b53]`, 即 `-p` 的参数
+//! - json: 带有来源压缩包, 条目及偏移的密钥文件
+
+use crate::keys::Keys;
+use core::fmt;
+use core::str::FromStr;
+use failure::{format_err, Error};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// 密钥文件的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeysFormat {
+    Bkcrack,
+    Pkcrack,
+    P7zip,
+    Json,
+}
+
+impl FromStr for KeysFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<KeysFormat, Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "bkcrack" | "rbkcrack" => Ok(KeysFormat::Bkcrack),
+            "pkcrack" => Ok(KeysFormat::Pkcrack),
+            "p7zip" | "7z" => Ok(KeysFormat::P7zip),
+            "json" => Ok(KeysFormat::Json),
+            _ => Err(format_err!("unknown keys format '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for KeysFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            KeysFormat::Bkcrack => "bkcrack",
+            KeysFormat::Pkcrack => "pkcrack",
+            KeysFormat::P7zip => "p7zip",
+            KeysFormat::Json => "json",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 密钥及其来源
+///
+/// offset 为密钥在密文中的位置, 与 `-o` 一样不计加密头, None 表示由密码得到的密钥 (即 -12)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeysFile {
+    pub keys: Vec<Keys>,
+    pub archive: Option<String>,
+    pub entry: Option<String>,
+    pub offset: Option<i32>,
+}
+
+/// json 中的布局, 密钥为三个十六进制字符串
+#[derive(Serialize, Deserialize)]
+struct Json {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    archive: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    entry: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    offset: Option<i32>,
+    keys: Vec<[String; 3]>,
+}
+
+fn parse_hex(s: &str) -> Result<u32, Error> {
+    let s = s.trim();
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    u32::from_str_radix(digits, 16).map_err(|_| format_err!("invalid key '{}'", s))
+}
+
+fn from_words(words: &[&str]) -> Result<Keys, Error> {
+    if words.len() != 3 {
+        return Err(format_err!("expected three keys, found {}", words.len()));
+    }
+    let words = words
+        .iter()
+        .map(|w| parse_hex(w))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(words.into_iter().collect())
+}
+
+/// 解析一行 bkcrack, pkcrack 或 p7zip 格式的密钥
+fn parse_line(line: &str) -> Result<Keys, Error> {
+    if let Some(start) = line.find("key0=") {
+        // pkcrack 会在前面加上 "Ta-daaaaa! " 之类的话
+        let words = line[start..]
+            .split(',')
+            .enumerate()
+            .map(|(i, part)| {
+                let name = format!("key{}=", i);
+                part.trim()
+                    .strip_prefix(name.as_str())
+                    .ok_or_else(|| format_err!("expected {} in '{}'", name, line))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        return from_words(&words);
+    }
+    if let (Some(start), Some(end)) = (line.find('['), line.rfind(']')) {
+        if start < end {
+            let words = line[start + 1..end].split('_').collect::<Vec<_>>();
+            return from_words(&words);
+        }
+    }
+    let line = line.trim();
+    let line = line.strip_prefix("Keys:").unwrap_or(line);
+    from_words(&line.split_whitespace().collect::<Vec<_>>())
+}
+
+impl KeysFile {
+    /// 解析密钥文件, 自动识别格式
+    ///
+    /// 非 json 格式时每行一组密钥, 忽略空行和 `#` 开头的行
+    pub fn parse(text: &str) -> Result<KeysFile, Error> {
+        if text.trim_start().starts_with('{') {
+            let json: Json = serde_json::from_str(text)
+                .map_err(|e| format_err!("invalid json keys file: {}", e))?;
+            let keys = json
+                .keys
+                .iter()
+                .map(|words| from_words(&[&words[0], &words[1], &words[2]]))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(KeysFile {
+                keys,
+                archive: json.archive,
+                entry: json.entry,
+                offset: json.offset,
+            });
+        }
+
+        let keys = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(parse_line)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(KeysFile {
+            keys,
+            ..Default::default()
+        })
+    }
+
+    pub fn read(path: &str) -> Result<KeysFile, Error> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format_err!("cannot read {}: {}", path, e))?;
+        let keys_file = KeysFile::parse(&text)?;
+        if keys_file.keys.is_empty() {
+            return Err(format_err!("no keys in {}", path));
+        }
+        Ok(keys_file)
+    }
+
+    /// 以 format 格式输出, 只有 json 会保留来源
+    pub fn format(&self, format: KeysFormat) -> String {
+        let hex = |keys: &Keys| {
+            [
+                format!("{:08x}", keys.get_x()),
+                format!("{:08x}", keys.get_y()),
+                format!("{:08x}", keys.get_z()),
+            ]
+        };
+        if format == KeysFormat::Json {
+            let json = Json {
+                archive: self.archive.clone(),
+                entry: self.entry.clone(),
+                offset: self.offset,
+                keys: self.keys.iter().map(hex).collect(),
+            };
+            return serde_json::to_string_pretty(&json).unwrap() + "\n";
+        }
+
+        self.keys
+            .iter()
+            .map(|keys| {
+                let [x, y, z] = hex(keys);
+                match format {
+                    KeysFormat::Bkcrack => format!("{} {} {}\n", x, y, z),
+                    KeysFormat::Pkcrack => format!("key0={}, key1={}, key2={}\n", x, y, z),
+                    _ => format!("[{}_{}_{}]\n", x, y, z),
+                }
+            })
+            .collect()
+    }
+
+    pub fn write(&self, path: &str, format: KeysFormat) -> Result<(), Error> {
+        fs::write(path, self.format(format))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeysFile, KeysFormat};
+    use crate::keys::Keys;
+
+    fn keys_file() -> KeysFile {
+        KeysFile {
+            keys: vec![
+                vec![0x8879dfed, 0x14335b6b, 0x8dc58b53]
+                    .into_iter()
+                    .collect(),
+                vec![0x0000_0001, 0xffff_ffff, 0x0abc_def0]
+                    .into_iter()
+                    .collect(),
+            ],
+            archive: Some("cipher.zip".into()),
+            entry: Some("file".into()),
+            offset: Some(1000),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let keys_file = keys_file();
+        for &format in &[
+            KeysFormat::Bkcrack,
+            KeysFormat::Pkcrack,
+            KeysFormat::P7zip,
+            KeysFormat::Json,
+        ] {
+            let parsed = KeysFile::parse(&keys_file.format(format)).unwrap();
+            if format == KeysFormat::Json {
+                assert_eq!(keys_file, parsed);
+            } else {
+                assert_eq!(keys_file.keys, parsed.keys, "{}", format);
+            }
+            assert_eq!(format, format.to_string().parse().unwrap());
+        }
+        assert_eq!(
+            "[8879dfed_14335b6b_8dc58b53]\n",
+            KeysFile {
+                keys: keys_file.keys[..1].to_vec(),
+                ..Default::default()
+            }
+            .format(KeysFormat::P7zip)
+        );
+    }
+
+    #[test]
+    fn parse_other_tools() {
+        let expected: Keys = vec![0x8879dfed, 0x14335b6b, 0x8dc58b53]
+            .into_iter()
+            .collect();
+        for text in &[
+            "Keys: 8879dfed 14335b6b 8dc58b53",
+            "# found by bkcrack\n\n8879dfed 14335b6b 8dc58b53\n",
+            "Ta-daaaaa! key0=8879dfed, key1=14335b6b, key2=8dc58b53",
+            "7za e cipher.zip '-p[8879dfed_14335b6b_8dc58b53]'",
+            r#"{"keys": [["0x8879dfed", "14335b6b", "8dc58b53"]]}"#,
+        ] {
+            let parsed = KeysFile::parse(text).unwrap();
+            assert_eq!(vec![expected.clone()], parsed.keys, "{}", text);
+        }
+
+        assert!(KeysFile::parse("8879dfed 14335b6b").is_err());
+        assert!(KeysFile::parse("key0=8879dfed, key2=8dc58b53").is_err());
+        assert!(KeysFile::parse("[8879dfed_zzzzzzzz_8dc58b53]").is_err());
+        assert!(KeysFile::parse(r#"{"keys": [["1", "2"]]}"#).is_err());
+        assert!("lzma".parse::<KeysFormat>().is_err());
+    }
+}
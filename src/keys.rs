@@ -2,10 +2,11 @@ use crate::crc32_tab::CRC32TAB;
 use crate::keystream_tab::KEYSTREAMTAB;
 use crate::mult_tab::MultTab;
 use crate::utils::*;
-use std::fmt;
-use std::iter::FromIterator;
+use core::fmt;
+use core::iter::FromIterator;
 
 /// Keys defining the cipher state
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Keys {
     x: u32,
     y: u32,
@@ -47,25 +48,73 @@ impl Keys {
         }
     }
 
+    /// Keys initialized with a password
+    pub fn from_password(password: &[u8]) -> Keys {
+        let mut keys = Keys::new();
+        for &p in password {
+            keys.update(p);
+        }
+        keys
+    }
+
     pub fn set_keys(&mut self, x: u32, y: u32, z: u32) {
         self.x = x;
         self.y = y;
         self.z = z;
     }
 
-    // TODO: 这一堆 wrapping, 真的会溢出? 为啥会溢出?
+    // 所有运算都是模 2^32 的, 任意的 Y 都可能溢出
     /// Update the state with a plaintext byte
     pub fn update(&mut self, p: u8) {
         self.x = CRC32TAB.crc32(self.x, p);
-        self.y = (self.y + u32::from(lsb(self.x))).wrapping_mul(MultTab::MULT) + 1;
+        self.y = self
+            .y
+            .wrapping_add(u32::from(lsb(self.x)))
+            .wrapping_mul(MultTab::MULT)
+            .wrapping_add(1);
         self.z = CRC32TAB.crc32(self.z, msb(self.y));
     }
 
     /// Update the state backward with a ciphertext byte
     pub fn update_backword(&mut self, c: u8) {
+        let z = CRC32TAB.crc32inv(self.z, msb(self.y));
+        self.update_backword_plain(c ^ KEYSTREAMTAB.get_byte(z));
+    }
+
+    /// Update the state backward with a plaintext byte
+    pub fn update_backword_plain(&mut self, p: u8) {
         self.z = CRC32TAB.crc32inv(self.z, msb(self.y));
-        self.y = (self.y - 1).wrapping_mul(MultTab::MULTINV) - u32::from(lsb(self.x));
-        self.x = CRC32TAB.crc32inv(self.x, c ^ KEYSTREAMTAB.get_byte(self.z));
+        self.y = self
+            .y
+            .wrapping_sub(1)
+            .wrapping_mul(MultTab::MULTINV)
+            .wrapping_sub(u32::from(lsb(self.x)));
+        self.x = CRC32TAB.crc32inv(self.x, p);
+    }
+
+    /// Decipher data in place, updating the state with the deciphered bytes
+    pub fn decipher(&mut self, data: &mut [u8]) {
+        for b in data {
+            *b ^= KEYSTREAMTAB.get_byte(self.z);
+            self.update(*b);
+        }
+    }
+
+    /// Move the state from position `from` to position `to` of cipher_text
+    ///
+    /// Positions count from the beginning of the encryption header, 0 being the keys derived from
+    /// the password. Going forward deciphers the bytes in between, going backward only needs the
+    /// ciphertext.
+    pub fn seek(&mut self, cipher_text: &[u8], from: usize, to: usize) {
+        if from <= to {
+            for &c in &cipher_text[from..to] {
+                self.update(c ^ KEYSTREAMTAB.get_byte(self.z));
+            }
+        } else {
+            for &c in cipher_text[to..from].iter().rev() {
+                self.update_backword(c);
+            }
+        }
     }
 
     /// return X value
@@ -90,10 +139,83 @@ impl Keys {
 #[cfg(test)]
 mod tests {
     use super::Keys;
+    use crate::keystream_tab::KEYSTREAMTAB;
+    use alloc::vec::Vec;
+    use proptest::prelude::*;
 
     #[test]
     fn update() {
-        let _key: Keys = Default::default();
-        // TODO: Finish this
+        let mut keys = Keys::new();
+        keys.update(0);
+        assert_eq!(
+            (0x5ecc_cd58, 0xc0f3_0b66, 0x6b5f_078c),
+            (keys.get_x(), keys.get_y(), keys.get_z())
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn update_backword_inverts_update(
+            x: u32,
+            y: u32,
+            z: u32,
+            plain_text in proptest::collection::vec(any::<u8>(), 1..64),
+        ) {
+            let mut keys = Keys::new();
+            keys.set_keys(x, y, z);
+
+            let cipher_text = plain_text
+                .iter()
+                .map(|&p| {
+                    let c = p ^ KEYSTREAMTAB.get_byte(keys.get_z());
+                    keys.update(p);
+                    c
+                })
+                .collect::<Vec<_>>();
+            for &c in cipher_text.iter().rev() {
+                keys.update_backword(c);
+            }
+
+            prop_assert_eq!((x, y, z), (keys.get_x(), keys.get_y(), keys.get_z()));
+        }
+
+        #[test]
+        fn update_backword_plain_inverts_update(
+            x: u32,
+            y: u32,
+            z: u32,
+            plain_text in proptest::collection::vec(any::<u8>(), 1..64),
+        ) {
+            let mut keys = Keys::new();
+            keys.set_keys(x, y, z);
+
+            for &p in &plain_text {
+                keys.update(p);
+            }
+            for &p in plain_text.iter().rev() {
+                keys.update_backword_plain(p);
+            }
+
+            prop_assert_eq!((x, y, z), (keys.get_x(), keys.get_y(), keys.get_z()));
+        }
+
+        #[test]
+        fn seek_matches_decipher(
+            password in proptest::collection::vec(any::<u8>(), 0..16),
+            cipher_text in proptest::collection::vec(any::<u8>(), 0..64),
+            from in 0..64usize,
+            to in 0..64usize,
+        ) {
+            let (from, to) = (from.min(cipher_text.len()), to.min(cipher_text.len()));
+            let keys_at = |position: usize| {
+                let mut keys = Keys::from_password(&password);
+                keys.decipher(&mut cipher_text[..position].to_vec());
+                keys
+            };
+
+            let mut keys = keys_at(from);
+            keys.seek(&cipher_text, from, to);
+            prop_assert_eq!(keys_at(to), keys);
+        }
     }
 }
@@ -1,38 +1,54 @@
 use crate::utils::*;
-use lazy_static::lazy_static;
 
-lazy_static! {
-    pub static ref KEYSTREAMTAB: KeystreamTab = KeystreamTab::new();
-}
+pub static KEYSTREAMTAB: KeystreamTab = KeystreamTab::new();
 
 /// Lookup tables for keystream related computations
 pub struct KeystreamTab {
     keystreamtab: [u8; 1 << 14],
+    /// Zi[2,16) values giving ki, sorted so they are grouped by Zi[10,16)
     keystreaminvtab: [[u32; 64]; 256],
-    keystreaminvfiltertab: Vec<Vec<Vec<u32>>>, //[[Vec<u32>; 64]; 256]
-    // TODO: bitset
-    keystreaminvexists: [[bool; 64]; 256],
+    /// the group of (ki, Zi[10,16)) is
+    /// keystreaminvtab[ki][keystreaminvoffsets[ki][j]..keystreaminvoffsets[ki][j + 1]]
+    /// with j = Zi[10,16)
+    keystreaminvoffsets: [[u8; 65]; 256],
+    /// bit j of keystreaminvexists[ki] is set if the group of (ki, j) is not empty
+    keystreaminvexists: [u64; 256],
 }
 
 impl KeystreamTab {
-    pub fn new() -> KeystreamTab {
+    /// Compute the lookup tables, evaluated at compile time for `KEYSTREAMTAB`
+    pub const fn new() -> KeystreamTab {
         let mut keystreamtab = KeystreamTab {
             keystreamtab: [0; 1 << 14],
             keystreaminvtab: [[0; 64]; 256],
-            keystreaminvfiltertab: (0..256)
-                .map(|_| (0..64).map(|_| vec![]).collect::<Vec<_>>())
-                .collect::<Vec<_>>(),
-            keystreaminvexists: [[false; 64]; 256],
+            keystreaminvoffsets: [[0; 65]; 256],
+            keystreaminvexists: [0; 256],
         };
 
+        // fill the tables and count the size of each group
         let mut next = [0; 256];
-        for z_2_16 in (0..1 << 16).step_by(4) {
-            let k = lsb(((z_2_16 | 2) * (z_2_16 | 3)) >> 8);
-            keystreamtab.keystreamtab[(z_2_16 >> 2) as usize] = k;
-            keystreamtab.keystreaminvtab[k as usize][next[k as usize]] = z_2_16;
-            keystreamtab.keystreaminvfiltertab[k as usize][(z_2_16 >> 10) as usize].push(z_2_16);
-            keystreamtab.keystreaminvexists[k as usize][(z_2_16 >> 10) as usize] = true;
-            next[k as usize] += 1;
+        let mut z_2_16 = 0;
+        while z_2_16 < 1 << 16 {
+            let k = lsb(((z_2_16 | 2) * (z_2_16 | 3)) >> 8) as usize;
+            let j = (z_2_16 >> 10) as usize;
+            keystreamtab.keystreamtab[(z_2_16 >> 2) as usize] = k as u8;
+            keystreamtab.keystreaminvtab[k][next[k]] = z_2_16;
+            keystreamtab.keystreaminvoffsets[k][j + 1] += 1;
+            keystreamtab.keystreaminvexists[k] |= 1 << j;
+            next[k] += 1;
+            z_2_16 += 4;
+        }
+
+        // turn the sizes into offsets
+        let mut k = 0;
+        while k < 256 {
+            let mut j = 0;
+            while j < 64 {
+                keystreamtab.keystreaminvoffsets[k][j + 1] +=
+                    keystreamtab.keystreaminvoffsets[k][j];
+                j += 1;
+            }
+            k += 1;
         }
 
         keystreamtab
@@ -52,23 +68,34 @@ impl KeystreamTab {
         self.keystreaminvtab[ki as usize]
     }
 
-    /// **return** a vector of Zi[2,16) values having given [10,16) bits
+    /// **return** a slice of Zi[2,16) values having given [10,16) bits
     /// such that getByte(zi) is equal to ki
-    /// **note** the vector contains one element on average
+    /// **note** the slice contains one element on average
     #[inline]
-    pub fn get_zi_2_16_vector(&self, ki: u8, zi_10_16: u32) -> &Vec<u32> {
-        &self.keystreaminvfiltertab[ki as usize][((zi_10_16 & MASK_0_16) >> 10) as usize]
+    pub fn get_zi_2_16_vector(&self, ki: u8, zi_10_16: u32) -> &[u32] {
+        let offsets = &self.keystreaminvoffsets[ki as usize];
+        let j = ((zi_10_16 & MASK_0_16) >> 10) as usize;
+        &self.keystreaminvtab[ki as usize][offsets[j] as usize..offsets[j + 1] as usize]
     }
 
     #[inline]
     pub fn has_zi_2_16(&self, ki: u8, zi_10_16: u32) -> bool {
-        self.keystreaminvexists[ki as usize][((zi_10_16 & MASK_0_16) >> 10) as usize]
+        self.keystreaminvexists[ki as usize] >> ((zi_10_16 & MASK_0_16) >> 10) & 1 != 0
+    }
+
+    /// **return** a bitset whose bit j is set if some Zi[2,16) value with
+    /// Zi[10,16) equal to j is such that getByte(zi) is equal to ki
+    #[inline]
+    pub fn get_zi_10_16_bitset(&self, ki: u8) -> u64 {
+        self.keystreaminvexists[ki as usize]
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::KeystreamTab;
+    use super::{KeystreamTab, KEYSTREAMTAB};
+    use alloc::vec;
+    use proptest::prelude::*;
 
     #[test]
     fn get_byte() {
@@ -95,4 +122,36 @@ mod test {
             KeystreamTab::new().get_zi_2_16_vector(167, 243712)
         );
     }
+
+    #[test]
+    fn has_zi_2_16() {
+        let instance = KeystreamTab::new();
+        for ki in 0..=255 {
+            for j in 0..64 {
+                let zi_10_16 = j << 10;
+                assert_eq!(
+                    !instance.get_zi_2_16_vector(ki, zi_10_16).is_empty(),
+                    instance.has_zi_2_16(ki, zi_10_16)
+                );
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn get_zi_2_16_vector_maps_back(ki: u8, zi_10_16: u32) {
+            for &zi_2_16 in KEYSTREAMTAB.get_zi_2_16_vector(ki, zi_10_16) {
+                prop_assert_eq!(ki, KEYSTREAMTAB.get_byte(zi_2_16));
+                prop_assert_eq!(zi_10_16 & 0xfc00, zi_2_16 & 0xfc00);
+                prop_assert_eq!(0, zi_2_16 & !0xfffc);
+            }
+        }
+
+        #[test]
+        fn get_zi_2_16_vector_is_complete(zi: u32) {
+            let ki = KEYSTREAMTAB.get_byte(zi);
+            prop_assert!(KEYSTREAMTAB.get_zi_2_16_vector(ki, zi).contains(&(zi & 0xfffc)));
+            prop_assert!(KEYSTREAMTAB.has_zi_2_16(ki, zi));
+        }
+    }
 }
@@ -1,16 +1,28 @@
-extern crate structopt;
+//! ZipCrypto 的已知明文攻击
+//!
+//! 核心部分 (`Keys`, 查找表, `Zreduction`, `Attack`) 只依赖 `core` 和 `alloc`,
+//! 读取 zip 文件 (`zip`), 并行 (`parallel`), 由密钥反推密码 (`password`), 解压整个压缩包 (`extract`)
+//! 和命令行 (`cli`) 由 cargo features 开启
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "cli")]
 use structopt::StructOpt;
 
 pub use crate::attack::Attack;
+#[cfg(feature = "zip")]
 pub use crate::data::Data;
 pub use crate::keys::Keys;
 pub use crate::keystream_tab::KEYSTREAMTAB;
-pub use crate::zreduction::Zreduction;
-use std::num::ParseIntError;
+pub use crate::zreduction::{Direction, Zreduction};
 
 mod attack;
+#[doc(hidden)]
+pub mod baseline;
 mod crc32_tab;
+#[cfg(feature = "zip")]
 mod data;
 mod keys;
 mod keystream_tab;
@@ -18,36 +30,62 @@ mod mult_tab;
 mod utils;
 mod zreduction;
 
+#[cfg(feature = "zip")]
+pub mod deflate;
+#[cfg(feature = "password")]
+pub mod encoding;
+#[cfg(feature = "extract")]
+pub mod extract;
+#[cfg(feature = "zip")]
 pub mod file;
+#[cfg(feature = "zip")]
+pub mod keyfile;
+#[cfg(feature = "password")]
+pub mod password;
+#[cfg(feature = "zip")]
+pub mod plain;
+#[cfg(feature = "zip")]
+pub mod volume;
 
-fn parse_hex(src: &str) -> Result<u32, ParseIntError> {
-    u32::from_str_radix(src, 16)
-}
-
+#[cfg(feature = "cli")]
 #[derive(StructOpt, Debug, Default)]
 #[structopt(name = "rbkcrack")]
 pub struct Arguments {
-    /// File containing the ciphertext
+    /// File containing the ciphertext ('-' for stdin)
     #[structopt(
         short = "c",
         long,
-        required_unless = "auto_find",
+        raw(required_unless_one = r#"&["keys", "auto_find", "list", "wordlist", "mask"]"#),
         allow_hyphen_values = true
     )]
     pub cipher_file: Option<String>,
 
-    /// File containing the known plaintext
+    /// File containing the known plaintext ('-' for stdin), or the plaintext itself as hex:..., text:... or base64:...
     #[structopt(
         short = "p",
         long,
-        raw(required_unless_one = r#"&["keys", "auto_find"]"#),
+        raw(
+            required_unless_one = r#"&["keys", "auto_find", "list", "plain_spec", "nested", "deflate", "wordlist", "mask"]"#
+        ),
         allow_hyphen_values = true
     )]
     pub plain_file: Option<String>,
 
-    /// Internal password representation as three 32-bits integers in hexadecimal (requires -d)
-    #[structopt(short = "k", long, parse(try_from_str = "parse_hex"))]
-    pub keys: Vec<u32>,
+    /// File listing known plaintext fragments, one "offset plaintext" per line ('-' for stdin)
+    #[structopt(short = "s", long)]
+    pub plain_spec: Option<String>,
+
+    /// Use the local header of the first entry of a zip, jar, docx, apk... stored in cipher_file as plaintext: 'auto' to guess it from the name of cipher_file, or comma separated name=..., version=N, flags=N, method=stored|deflate|N and extra=HEX
+    #[structopt(long)]
+    pub nested: Option<plain::NestedHeader>,
+
+    /// Use the structure of the deflate stream in cipher_file as plaintext: 'stored' for stored blocks, or 'fixed' for a fixed Huffman block encoding the plaintext given with -p, followed by comma separated size=N (uncompressed size), block=N (stored) or last=0|1 (fixed)
+    #[structopt(long, conflicts_with = "nested")]
+    pub deflate: Option<deflate::DeflateHint>,
+
+    /// Internal password representation as three 32-bits integers in hexadecimal, or a file of keys in bkcrack, pkcrack, p7zip or json format (requires -d, -x, -w, -m or --save_keys)
+    #[structopt(short = "k", long)]
+    pub keys: Vec<String>,
 
     /// Zip archive containing cipher_file
     #[structopt(short = "C", long)]
@@ -61,6 +99,18 @@ pub struct Arguments {
     #[structopt(short = "o", long, allow_hyphen_values = true)]
     pub offset: Option<i32>,
 
+    /// Offset of the keys printed and of the keys given with -k unless the keys file tells it, relative to ciphertext without encryption header (default: -12, the keys derived from the password)
+    #[structopt(long, allow_hyphen_values = true)]
+    pub keys_offset: Option<i32>,
+
+    /// File to save the keys to, with the archive, the entry and the offset in json format
+    #[structopt(long)]
+    pub save_keys: Option<String>,
+
+    /// Format of the saved keys: bkcrack, pkcrack, p7zip or json
+    #[structopt(long, requires = "save_keys")]
+    pub keys_format: Option<keyfile::KeysFormat>,
+
     /// Maximum number of bytes of plaintext to read
     #[structopt(short = "t", long)]
     pub plain_size: Option<usize>,
@@ -77,13 +127,98 @@ pub struct Arguments {
     #[structopt(short = "u", long)]
     pub unzip: bool,
 
+    /// Directory to extract all the entries of the encrypted zip archive to, using the keys
+    #[structopt(short = "x", long)]
+    pub extract: Option<String>,
+
     /// Find entry by CRC32 automatically
     #[structopt(short = "a", long)]
     pub auto_find: bool,
+
+    /// Wordlist to recover the password from the keys, one word per line ('-' for stdin)
+    #[structopt(short = "w", long)]
+    pub wordlist: Option<String>,
+
+    /// Mangling rules applied to each word of the wordlist: comma separated case, leet and digits=N
+    #[structopt(long, requires = "wordlist")]
+    pub rules: Option<password::Rules>,
+
+    /// Mask to recover the password from the keys, e.g. Acme?a?a?d?d (?l, ?u, ?d, ?s, ?a, ?e, ?b and ?1 to ?4)
+    #[structopt(short = "m", long, conflicts_with = "wordlist")]
+    pub mask: Option<String>,
+
+    /// Custom charset ?1 of the mask
+    #[structopt(short = "1", long, requires = "mask")]
+    pub charset1: Option<String>,
+
+    /// Custom charset ?2 of the mask
+    #[structopt(short = "2", long, requires = "mask")]
+    pub charset2: Option<String>,
+
+    /// Custom charset ?3 of the mask
+    #[structopt(short = "3", long, requires = "mask")]
+    pub charset3: Option<String>,
+
+    /// Custom charset ?4 of the mask
+    #[structopt(short = "4", long, requires = "mask")]
+    pub charset4: Option<String>,
+
+    /// Also try the shorter passwords matching the beginning of the mask
+    #[structopt(long, requires = "mask")]
+    pub increment: bool,
+
+    /// Encodings of the non-ASCII characters of the passwords, comma separated: utf-8, latin1, cp437, cp850, cp852, cp866, cp1250, cp1251 or cp1252
+    #[structopt(long, raw(use_delimiter = "true"))]
+    pub encoding: Vec<encoding::Encoding>,
+
+    /// Encoding of the wordlist: one of the above, utf-16le or utf-16be
+    #[structopt(long, requires = "wordlist")]
+    pub wordlist_encoding: Option<encoding::Encoding>,
+
+    /// Size under which the Z reduction only waits a limited number of steps for a smaller vector
+    #[structopt(long)]
+    pub wait_size: Option<usize>,
+
+    /// Size under which the Z reduction keeps track of the smallest vector
+    #[structopt(long)]
+    pub track_size: Option<usize>,
+
+    /// Keep reducing Z values only while the extra plaintext still shrinks them meaningfully
+    #[structopt(long)]
+    pub adaptive: bool,
+
+    /// Index of the plaintext from which the Z reduction starts (default: the last byte, or the first one with --forward)
+    #[structopt(long, allow_hyphen_values = true)]
+    pub reduction_start: Option<i32>,
+
+    /// Reduce Z values forward, towards the end of the plaintext
+    #[structopt(long)]
+    pub forward: bool,
+
+    /// Choose where the Z reduction starts and its direction by trying a few of them on a sample
+    #[structopt(long, raw(conflicts_with_all = r#"&["reduction_start", "forward"]"#))]
+    pub auto_start: bool,
+
+    /// File to write the size of the Z vector at each index of the reduction, as CSV
+    #[structopt(long)]
+    pub reduction_curve: Option<String>,
+
+    /// Number of threads used by the attack (default: number of CPUs)
+    #[structopt(long)]
+    pub threads: Option<usize>,
+
+    /// List entries of cipher_zip and exit
+    #[structopt(short = "L", long, requires = "cipher_zip")]
+    pub list: bool,
 }
 
+/// 输出进度, 没有任何工作时不输出
+#[cfg(feature = "std")]
 #[inline]
 pub fn progress(done: usize, total: usize) {
+    if total == 0 {
+        return;
+    }
     print!(
         "\r{:.2} % ({} / {})",
         done as f32 / total as f32 * 100.0,
@@ -92,7 +227,7 @@ pub fn progress(done: usize, total: usize) {
     );
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "cli"))]
 mod tests {
     use super::{Attack, Data, Zreduction};
     use crate::Arguments;
@@ -111,9 +246,9 @@ mod tests {
 
         let mut zr = Zreduction::new(&data.keystream);
         zr.generate();
-        zr.reduce();
+        zr.reduce(|_, _| ());
 
-        let mut attack = Attack::new(&data, zr.get_index() - 11);
+        let mut attack = Attack::new(&data.plain_text, &data.keystream, zr.get_index() - 11);
         for &it in zr.get_zi_2_32_vector() {
             if attack.carry_out(it) {
                 println!("\nfound!");
@@ -121,7 +256,7 @@ mod tests {
             }
         }
 
-        let keys = attack.get_keys();
+        let keys = attack.get_keys(&data.cipher_text, Data::HEADER_SIZE);
 
         assert_eq!(0x8879dfed, keys.get_x());
         assert_eq!(0x14335b6b, keys.get_y());
@@ -1,45 +1,147 @@
 use chrono::Local;
-use failure::Error;
+use failure::{format_err, Error};
 use flate2::write::DeflateDecoder;
+use flate2::{Crc, Decompress, FlushDecompress, Status};
 use log::debug;
-use rayon::prelude::*;
-use rbkcrack::{file, progress, Arguments, Attack, Data, Keys, Zreduction, KEYSTREAMTAB};
+use rbkcrack::encoding::Encoding;
+use rbkcrack::file::{Bytes, Encryption, ZipEntry};
+use rbkcrack::keyfile::{KeysFile, KeysFormat};
+use rbkcrack::password::{HeaderCheck, Target};
+use rbkcrack::volume::MultiVolume;
+use rbkcrack::{
+    encoding, extract, file, password, progress, Arguments, Attack, Data, Direction, Keys,
+    Zreduction,
+};
 use structopt::StructOpt;
 
+use std::cell::RefCell;
+use std::fs::File;
 use std::io::prelude::*;
-use std::io::stdout;
+use std::io::{stdin, stdout, BufReader};
+use std::path::Path;
 use std::process;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
 fn now() -> String {
     Local::now().format("%T").to_string()
 }
 
+/// number of Z values attacked by a thread at a time
+const BATCH_SIZE: usize = 16;
+
+/// interval between two progress reports
+const REPORT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// progress of a worker thread, aligned to avoid false sharing
+#[repr(align(64))]
+#[derive(Default)]
+struct Counter(AtomicUsize);
+
+/// 选择 Z reduction 的起点和方向
+fn reduction_start(
+    args: &Arguments,
+    data: &Data,
+    zr: &Zreduction,
+) -> Result<(usize, Direction), Error> {
+    // 明文正好是 Attack::SIZE 字节时没有 reduction 可做, 攻击只能从最后一个字节开始
+    let last = data.keystream.len() - 1;
+    if last == Attack::SIZE - 1 {
+        return Ok((last, Direction::Backward));
+    }
+
+    Ok(if args.auto_start {
+        let (start, direction) = zr.find_start();
+        println!(
+            "[{}] Z reduction chosen to start at index {} {}",
+            now(),
+            data.offset + start as i32,
+            if direction == Direction::Forward {
+                "forward"
+            } else {
+                "backward"
+            }
+        );
+        (start, direction)
+    } else {
+        let direction = if args.forward {
+            Direction::Forward
+        } else {
+            Direction::Backward
+        };
+        let start = match args.reduction_start {
+            Some(start) => {
+                let start = start - data.offset;
+                let first = if args.forward {
+                    0
+                } else {
+                    Attack::SIZE as i32 - 1
+                };
+                if start < first || start >= data.keystream.len() as i32 {
+                    return Err(format_err!("reduction start is out of the plaintext"));
+                }
+                start as usize
+            }
+            None if args.forward => 0,
+            None => data.keystream.len() - 1,
+        };
+        (start, direction)
+    })
+}
+
 fn find_keys(args: &Arguments) -> Result<Vec<Keys>, Error> {
     // load data
     let data = Data::new(args)?;
 
     // generate and reduce Zi[2,32) values
     let mut zr = Zreduction::new(&data.keystream);
-    zr.generate();
+    if let Some(wait_size) = args.wait_size {
+        zr.set_wait_size(wait_size);
+    }
+    if let Some(track_size) = args.track_size {
+        zr.set_track_size(track_size);
+    }
+    zr.set_adaptive(args.adaptive);
+
+    let (start, direction) = reduction_start(args, &data, &zr)?;
+    zr.generate_from(start, direction);
     println!("Generated {} Z values.", zr.size());
 
+    if !data.extra_plain.is_empty() {
+        println!(
+            "Using {} extra bytes of known plaintext to check the keys",
+            data.extra_plain.len()
+        );
+    }
+
     if data.keystream.len() > Attack::SIZE {
         println!(
-            "[{}] Z reduction using {} extra bytes of known plaintext",
+            "[{}] Z reduction using up to {} extra bytes of known plaintext",
             now(),
             data.keystream.len() - Attack::SIZE
         );
-        zr.reduce();
+        zr.reduce(progress);
         println!("\n{} values remaining.", zr.size());
+
+        if let Some(path) = &args.reduction_curve {
+            let mut output = file::open_output(path)?;
+            writeln!(output, "index,size")?;
+            for &(index, size) in zr.get_curve() {
+                writeln!(output, "{},{}", data.offset + index as i32, size)?;
+            }
+            output.flush()?;
+        }
     }
 
     // iterate over remaining Zi[2,32) values
-    let attack = Attack::new(&data, zr.get_index() + 1 - Attack::SIZE);
-    let done = Arc::new(AtomicUsize::new(1));
-    let should_stop = Arc::new(RwLock::new(false));
-    let size = zr.size();
+    let attack_index = (zr.get_index() + 1)
+        .checked_sub(Attack::SIZE)
+        .ok_or_else(|| format_err!("the Z reduction stopped before index {}", Attack::SIZE - 1))?;
+    let attack = Attack::new(&data.plain_text, &data.keystream, attack_index);
+    let candidates = zr.get_zi_2_32_vector();
+    let size = candidates.len();
     println!(
         "[{}] Attack on {} Z values at index {}",
         now(),
@@ -47,96 +149,509 @@ fn find_keys(args: &Arguments) -> Result<Vec<Keys>, Error> {
         data.offset + zr.get_index() as i32
     );
 
-    let mut keysvec = vec![];
+    let plain_start = (Data::HEADER_SIZE as i32 + data.offset) as usize;
+    let next = AtomicUsize::new(0);
+    let should_stop = AtomicBool::new(false);
+    let finished = AtomicBool::new(false);
+    let counters = (0..rayon::current_num_threads())
+        .map(|_| Counter::default())
+        .collect::<Vec<_>>();
+    let found = Mutex::new(vec![]);
 
-    // 将任务每 1000 个分为一组, 每组再并行检测
-    // 保证顺序大抵是从小到大的
-    for chunk in zr.get_zi_2_32_vector().chunks(1000) {
-        let tmp = chunk.into_par_iter().filter_map(|&z| {
-            if *should_stop.read().unwrap() {
-                return None;
+    thread::scope(|s| {
+        // 单独的线程定时汇总进度, 避免工作线程争抢 stdout
+        let reporter = s.spawn(|| {
+            let done = || counters.iter().map(|c| c.0.load(Ordering::Relaxed)).sum();
+            while !finished.load(Ordering::Relaxed) {
+                progress(done(), size);
+                stdout().flush().unwrap();
+                thread::park_timeout(REPORT_INTERVAL);
             }
+            progress(done(), size);
+        });
 
-            progress(done.fetch_add(1, Ordering::SeqCst), size);
-            stdout().flush().unwrap();
+        // 每个线程从 next 处领取一小批 Z 值
+        // 保证顺序大抵是从小到大的
+        rayon::scope(|rs| {
+            for counter in &counters {
+                let mut attack = attack.clone();
+                let (next, should_stop, found, data) = (&next, &should_stop, &found, &data);
+                rs.spawn(move |_| {
+                    while !should_stop.load(Ordering::Relaxed) {
+                        let start = next.fetch_add(BATCH_SIZE, Ordering::Relaxed);
+                        if start >= size {
+                            break;
+                        }
+                        let batch = &candidates[start..size.min(start + BATCH_SIZE)];
 
-            let mut attack = attack.clone();
-            if attack.carry_out(z) {
-                let possible_keys = attack.get_keys();
+                        let n = attack.carry_out_batch(batch, |i, attack| {
+                            let possible_keys = attack.get_keys(&data.cipher_text, plain_start);
+                            if !data.check_keys(&possible_keys) {
+                                return true;
+                            }
 
-                if args.exhaustive {
-                    println!("\rKeys: {}", possible_keys);
-                } else {
-                    *should_stop.write().unwrap() = true;
-                }
-                Some(possible_keys)
-            } else {
-                None
+                            if args.exhaustive {
+                                println!("\rKeys: {}", possible_keys);
+                            } else {
+                                should_stop.store(true, Ordering::Relaxed);
+                            }
+                            found.lock().unwrap().push((start + i, possible_keys));
+                            args.exhaustive
+                        });
+                        counter.0.fetch_add(n, Ordering::Relaxed);
+                    }
+                });
             }
         });
-        keysvec.par_extend(tmp);
-    }
+
+        finished.store(true, Ordering::Relaxed);
+        reporter.thread().unpark();
+    });
 
     if size != 0 {
         println!();
     }
 
+    let mut found = found.into_inner().unwrap();
+    found.sort_by_key(|&(i, _)| i);
+    let keysvec = found.into_iter().map(|(_, keys)| keys).collect();
+
     // return the keys
     Ok(keysvec)
 }
 
-fn decipher(args: &Arguments, keys: &mut Keys) -> Result<(), Error> {
-    let cipher_text =
-        if let (Some(zip_path), Some(entry_name)) = (&args.cipher_zip, &args.cipher_file) {
-            file::read_zip_entry(zip_path, entry_name, std::usize::MAX)?
-        } else {
-            file::read_raw_file(args.cipher_file.as_ref().unwrap(), std::usize::MAX)?
+/// 读取至多 size 字节的密文, 含加密头
+fn read_cipher(args: &Arguments, size: usize) -> Result<Bytes, Error> {
+    if let (Some(zip_path), Some(entry_name)) = (&args.cipher_zip, &args.cipher_file) {
+        file::read_zip_entry(zip_path, entry_name, size)
+    } else if let Some(cipher_file) = &args.cipher_file {
+        file::read_raw_file(cipher_file, size)
+    } else {
+        Err(format_err!("the ciphertext file is needed"))
+    }
+}
+
+/// 密钥偏移对应的密文位置, 从加密头开始计算
+fn keys_position(offset: Option<i32>) -> Result<Option<usize>, Error> {
+    match offset {
+        Some(offset) if offset < -(Data::HEADER_SIZE as i32) => {
+            Err(format_err!("keys offset is before the encryption header"))
+        }
+        Some(offset) => Ok(Some((Data::HEADER_SIZE as i32 + offset) as usize)),
+        None => Ok(None),
+    }
+}
+
+/// 把密钥在密文中从 from 移到 to
+///
+/// 位置相同时不需要密文, 比如没有 -c 时读取由密码得到的密钥
+fn seek_keys(args: &Arguments, keys: &mut Keys, from: usize, to: usize) -> Result<(), Error> {
+    if from == to {
+        return Ok(());
+    }
+    let end = from.max(to);
+    let cipher_text = read_cipher(args, end)?;
+    if cipher_text.len() < end {
+        return Err(format_err!(
+            "the ciphertext is too short for offset {}",
+            end as i32 - Data::HEADER_SIZE as i32
+        ));
+    }
+    keys.seek(&cipher_text, from, to);
+    Ok(())
+}
+
+/// 输出密钥, 指定了 --keys_offset 时输出该位置的密钥, 指定了 --save_keys 时同时保存
+fn report_keys(args: &Arguments, keysvec: &[Keys]) -> Result<(), Error> {
+    let mut keysvec = keysvec.to_vec();
+    match keys_position(args.keys_offset)? {
+        Some(position) => {
+            println!("[{}] Keys at offset {}", now(), args.keys_offset.unwrap());
+            for keys in &mut keysvec {
+                seek_keys(args, keys, 0, position)?;
+            }
+        }
+        None => println!("[{}] Keys", now()),
+    }
+    for keys in &keysvec {
+        println!("{}", keys);
+    }
+
+    if let Some(path) = &args.save_keys {
+        let keys_file = KeysFile {
+            keys: keysvec,
+            archive: args.cipher_zip.clone(),
+            entry: args.cipher_file.clone(),
+            offset: Some(args.keys_offset.unwrap_or(-(Data::HEADER_SIZE as i32))),
         };
+        keys_file.write(path, args.keys_format.unwrap_or(KeysFormat::Json))?;
+        println!("Saved the keys to {}", path);
+    }
+    Ok(())
+}
+
+/// 读取 -k 给出的密钥, 可以是三个十六进制数或密钥文件
+///
+/// 密钥文件中记录的条目作为 -C 和 -c 的默认值, 记录的偏移优先于 --keys_offset
+fn load_keys(args: &mut Arguments) -> Result<Vec<Keys>, Error> {
+    let keys_file = match args.keys.len() {
+        1 => KeysFile::read(&args.keys[0])?,
+        3 => KeysFile::parse(&args.keys.join(" "))?,
+        _ => return Err(format_err!("-k takes three keys or a keys file")),
+    };
+    if args.cipher_zip.is_none() && args.cipher_file.is_none() {
+        args.cipher_zip = keys_file.archive;
+        args.cipher_file = keys_file.entry;
+    }
+
+    // 其余操作都使用由密码得到的密钥
+    let mut keysvec = keys_file.keys;
+    if let Some(position) = keys_position(keys_file.offset.or(args.keys_offset))? {
+        for keys in &mut keysvec {
+            seek_keys(args, keys, position, 0)?;
+        }
+    }
+    Ok(keysvec)
+}
+
+fn decipher(args: &Arguments, keys: &mut Keys) -> Result<(), Error> {
+    let cipher_text = read_cipher(args, usize::MAX)?;
 
-    let mut deciphered_stream = file::open_output(args.deciphered_file.as_ref().unwrap())?;
+    let deciphered_stream = file::open_output(args.deciphered_file.as_ref().unwrap())?;
+    let mut output: Box<dyn Write> = if args.unzip {
+        debug!("decompressing");
+        Box::new(DeflateDecoder::new(deciphered_stream))
+    } else {
+        Box::new(deciphered_stream)
+    };
 
+    // 分块解密, 避免复制整个文件
     debug!("deciphering");
-    let decrypted_text = cipher_text
-        .into_iter()
-        .map(|b| {
-            let p = b ^ KEYSTREAMTAB.get_byte(keys.get_z());
-            keys.update(p);
-            p
-        })
-        .collect::<Vec<_>>();
+    let mut buffer = Vec::with_capacity(1 << 16);
+    for (i, chunk) in cipher_text.chunks(1 << 16).enumerate() {
+        buffer.clear();
+        buffer.extend_from_slice(chunk);
+        keys.decipher(&mut buffer);
+        // 跳过加密头
+        let skip = if i == 0 { Data::HEADER_SIZE } else { 0 };
+        output.write_all(&buffer[skip.min(buffer.len())..])?;
+    }
+    output.flush()?;
 
     debug!(
         "deciphered: {} bytes",
-        decrypted_text.len() - Data::HEADER_SIZE
+        cipher_text.len().saturating_sub(Data::HEADER_SIZE)
     );
-    if args.unzip {
-        debug!("decompressing");
-        let mut deflater = DeflateDecoder::new(deciphered_stream);
-        deflater.write_all(&decrypted_text[Data::HEADER_SIZE..])?;
+    Ok(())
+}
+
+fn search_wordlist<T: Target + ?Sized>(
+    args: &Arguments,
+    target: &T,
+) -> Result<Option<Vec<u8>>, Error> {
+    let wordlist = args.wordlist.as_ref().unwrap();
+    let rules = args.rules.clone().unwrap_or_default();
+    println!("[{}] Recovering the password from {}", now(), wordlist);
+
+    let stdin = stdin();
+    let reader: Box<dyn BufRead> = if wordlist == "-" {
+        Box::new(stdin.lock())
     } else {
-        deciphered_stream.write_all(&decrypted_text[Data::HEADER_SIZE..])?;
+        Box::new(BufReader::new(File::open(wordlist)?))
+    };
+    let encodings = password::Encodings {
+        wordlist: args.wordlist_encoding,
+        passwords: args.encoding.clone(),
+    };
+    let found = password::search_wordlist(reader, &rules, &encodings, target, |done| {
+        print!("\r{} words tried", done);
+        stdout().flush().unwrap();
+    })?;
+    println!();
+    Ok(found)
+}
+
+fn search_mask<T: Target + ?Sized>(args: &Arguments, target: &T) -> Result<Option<Vec<u8>>, Error> {
+    let custom = [
+        &args.charset1,
+        &args.charset2,
+        &args.charset3,
+        &args.charset4,
+    ]
+    .iter()
+    .map(|charset| charset.as_deref().unwrap_or(""))
+    .collect::<Vec<_>>();
+    let encodings = if args.encoding.is_empty() {
+        vec![Encoding::Utf8]
+    } else {
+        args.encoding.clone()
+    };
+    // 只含 ASCII 的 mask 在各编码下相同, 不必重复尝试
+    let mut masks: Vec<(Encoding, password::Mask)> = vec![];
+    for &encoding in &encodings {
+        let mask = password::Mask::with_encoding(args.mask.as_ref().unwrap(), &custom, encoding)?;
+        if masks.iter().all(|(_, m)| *m != mask) {
+            masks.push((encoding, mask));
+        }
+    }
+
+    for (encoding, mask) in &masks {
+        if let Some(found) = search_encoded_mask(args, *encoding, mask, target)? {
+            return Ok(Some(found));
+        }
+    }
+    Ok(None)
+}
+
+fn search_encoded_mask<T: Target + ?Sized>(
+    args: &Arguments,
+    encoding: Encoding,
+    mask: &password::Mask,
+    target: &T,
+) -> Result<Option<Vec<u8>>, Error> {
+    let first = if args.increment { 1 } else { mask.len() };
+    for len in first..=mask.len() {
+        let mask = mask.prefix(len);
+        let candidates = mask
+            .candidates()
+            .ok_or_else(|| format_err!("too many candidates for length {}", len))?;
+        println!(
+            "[{}] Trying {} passwords of length {} ({} candidates)",
+            now(),
+            encoding,
+            len,
+            candidates
+        );
+
+        let start = Instant::now();
+        let found = mask.search(target, |done, total| {
+            // 按目前的速度估计剩余时间
+            let elapsed = start.elapsed().as_secs_f64();
+            let eta = elapsed / done as f64 * (total - done) as f64;
+            progress(done as usize, total as usize);
+            print!(" ETA {}", format_duration(eta as u64));
+            stdout().flush().unwrap();
+        })?;
+        println!();
+        if found.is_some() {
+            return Ok(found);
+        }
+    }
+    Ok(None)
+}
+
+fn format_duration(secs: u64) -> String {
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn recover_password<T: Target + ?Sized>(
+    args: &Arguments,
+    target: &T,
+) -> Result<Option<Vec<u8>>, Error> {
+    let found = if args.mask.is_some() {
+        search_mask(args, target)?
+    } else {
+        search_wordlist(args, target)?
+    };
+
+    match &found {
+        Some(password) => {
+            println!("[{}] Password", now());
+            println!("as bytes: {}", hex(password));
+            if password.is_ascii() {
+                println!("as text: {}", String::from_utf8_lossy(password));
+            } else {
+                for (encoding, text) in encoding::plausible_decodings(password) {
+                    println!("as {}: {}", encoding, text);
+                }
+            }
+        }
+        None => println!("Could not find the password."),
+    }
+    Ok(found)
+}
+
+/// 没有已知明文时, 用 cipher_zip 中所有 ZipCrypto 条目的加密头检验密码
+fn header_check(args: &Arguments) -> Result<HeaderCheck<impl Fn(&Keys) -> bool + Sync>, Error> {
+    let path = args
+        .cipher_zip
+        .as_ref()
+        .ok_or_else(|| format_err!("the encrypted zip archive is needed to guess the password"))?;
+    let entries = file::read_entries(path)?
+        .into_iter()
+        .filter(|entry| entry.info.encryption() == Encryption::ZipCrypto)
+        .collect::<Vec<_>>();
+    if entries.is_empty() {
+        return Err(format_err!(
+            "could not find any entry encrypted with ZipCrypto"
+        ));
+    }
+
+    let mut reader = MultiVolume::open(path)?;
+    let mut headers = vec![];
+    for entry in &entries {
+        let data = entry.read(&mut reader, Data::HEADER_SIZE)?;
+        if data.len() < Data::HEADER_SIZE {
+            return Err(format_err!("{} is too small", entry.info.name));
+        }
+        let mut header = [0; Data::HEADER_SIZE];
+        header.copy_from_slice(&data);
+        headers.push((header, entry.check_byte()));
+    }
+
+    // 通过所有校验字节的密码再解密最小的条目, 检查 CRC32
+    let smallest = entries
+        .iter()
+        .filter(|entry| entry.info.method == 0 || entry.info.method == 8)
+        .min_by_key(|entry| entry.compressed_size);
+    let confirm = match smallest {
+        Some(entry) => {
+            println!(
+                "Checking the headers of {} entries and the CRC32 of {}",
+                entries.len(),
+                entry.info.name
+            );
+            Some((entry.clone(), entry.read(&mut reader, usize::MAX)?))
+        }
+        None => {
+            println!(
+                "Checking the headers of {} entries, no entry to check the CRC32",
+                entries.len()
+            );
+            None
+        }
+    };
+
+    Ok(HeaderCheck::new(headers, move |keys: &Keys| {
+        confirm
+            .as_ref()
+            .map_or(true, |(entry, data)| check_crc(keys, entry, data))
+    }))
+}
+
+thread_local! {
+    /// 解压器的内部状态很大, 每个线程只创建一次
+    static INFLATE: RefCell<(Decompress, Vec<u8>)> =
+        RefCell::new((Decompress::new(false), vec![0; 1 << 15]));
+}
+
+/// 解密 (并解压) 整个条目, 检查其 CRC32
+fn check_crc(keys: &Keys, entry: &ZipEntry, cipher_text: &[u8]) -> bool {
+    INFLATE.with(|inflate| {
+        let (inflate, output) = &mut *inflate.borrow_mut();
+        inflate.reset(false);
+        let mut keys = keys.clone();
+        let mut crc = Crc::new();
+        let mut finished = entry.info.method != 8;
+
+        // 分块解密, 错误的密码通常很快就会让解压失败
+        let mut buffer = Vec::with_capacity(1 << 8);
+        for (i, chunk) in cipher_text.chunks(1 << 8).enumerate() {
+            buffer.clear();
+            buffer.extend_from_slice(chunk);
+            keys.decipher(&mut buffer);
+            let skip = if i == 0 { Data::HEADER_SIZE } else { 0 };
+            let mut plain_text = &buffer[skip.min(buffer.len())..];
+            if entry.info.method != 8 {
+                crc.update(plain_text);
+                continue;
+            }
+
+            while !plain_text.is_empty() && !finished {
+                let (total_in, total_out) = (inflate.total_in(), inflate.total_out());
+                let status = match inflate.decompress(plain_text, output, FlushDecompress::None) {
+                    Ok(status) => status,
+                    Err(_) => return false,
+                };
+                let read = (inflate.total_in() - total_in) as usize;
+                let written = (inflate.total_out() - total_out) as usize;
+                crc.update(&output[..written]);
+                plain_text = &plain_text[read..];
+                finished = status == Status::StreamEnd;
+                if read == 0 && written == 0 && !finished {
+                    return false;
+                }
+            }
+        }
+        finished && crc.sum() == entry.crc32
+    })
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn list(path: &str) -> Result<(), Error> {
+    let entries = file::read_entries(path)?;
+    println!(
+        "{:>8} {:>12} {:>12}  {:<20} Name",
+        "CRC32", "Offset", "Size", "Encryption"
+    );
+    for entry in entries {
+        println!(
+            "{:08x} {:>12} {:>12}  {:<20} {}",
+            entry.crc32,
+            entry.data_start,
+            entry.compressed_size,
+            entry.info.encryption().to_string(),
+            entry.info.name
+        );
     }
     Ok(())
 }
 
 fn run() -> Result<(), Error> {
-    env_logger::init();
+    env_logger::Builder::from_env(
+        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "rbkcrack=info"),
+    )
+    .init();
 
-    let args: Arguments = Arguments::from_args();
+    let mut args: Arguments = Arguments::from_args();
 
     debug!("{:?}", args);
 
+    if args.list {
+        return list(args.cipher_zip.as_ref().unwrap());
+    }
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()?;
+    }
+
     let mut keysvec = vec![];
 
-    if args.keys.len() == 3 {
-        keysvec.push(args.keys.iter().cloned().collect::<Keys>());
+    // 没有密钥也没有任何明文时只能猜测密码
+    let password_only = args.keys.is_empty()
+        && args.plain_file.is_none()
+        && args.plain_spec.is_none()
+        && args.nested.is_none()
+        && args.deflate.is_none()
+        && !args.auto_find
+        && (args.wordlist.is_some() || args.mask.is_some());
+
+    if !args.keys.is_empty() {
+        keysvec = load_keys(&mut args)?;
+        if args.save_keys.is_some() {
+            report_keys(&args, &keysvec)?;
+        }
+    } else if password_only {
+        match recover_password(&args, &header_check(&args)?)? {
+            Some(password) => {
+                let keys = Keys::from_password(&password);
+                report_keys(&args, std::slice::from_ref(&keys))?;
+                keysvec.push(keys);
+            }
+            None => process::exit(1),
+        }
     } else {
         let result = find_keys(&args)?;
         if !result.is_empty() {
-            println!("[{}] Keys", now());
-            for keys in &result {
-                println!("{}", keys);
-            }
+            report_keys(&args, &result)?;
             keysvec.extend(result);
         } else {
             eprintln!("Could not find the keys.");
@@ -152,6 +667,36 @@ fn run() -> Result<(), Error> {
         decipher(&args, &mut keysvec[0])?;
         println!("Wrote deciphered text.");
     }
+
+    if let Some(dir) = &args.extract {
+        let path = args
+            .cipher_zip
+            .as_ref()
+            .ok_or_else(|| format_err!("the encrypted zip archive is needed to extract"))?;
+        if keysvec.len() > 1 {
+            println!("Extracting using the keys {}", keysvec[0]);
+        }
+        println!("[{}] Extracting {} to {}", now(), path, dir);
+        let failed =
+            extract::extract_all(
+                path,
+                &keysvec[0],
+                Path::new(dir),
+                |entry, result| match result {
+                    Ok(()) => println!("{}", entry.info.name),
+                    Err(e) => println!("{}: {}", entry.info.name, e),
+                },
+            )?;
+        if failed == 0 {
+            println!("Extracted all the entries.");
+        } else {
+            println!("{} entries could not be extracted.", failed);
+        }
+    }
+
+    if !password_only && (args.wordlist.is_some() || args.mask.is_some()) {
+        recover_password(&args, &keysvec[..])?;
+    }
     Ok(())
 }
 
@@ -161,3 +706,79 @@ fn main() {
         Err(e) => eprintln!("{}", e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{load_keys, reduction_start, report_keys};
+    use rbkcrack::file::Bytes;
+    use rbkcrack::plain::Fragment;
+    use rbkcrack::{Arguments, Attack, Data, Direction, Keys, Zreduction};
+    use std::{env, fs, process};
+
+    #[test]
+    fn forward_reduction_start() {
+        let data = |len: usize| {
+            let fragment = Fragment {
+                offset: 0,
+                data: Bytes::from(vec![0; len]),
+            };
+            Data::from_fragments(Bytes::from(vec![0; 12 + len]), &[fragment]).unwrap()
+        };
+        let forward = Arguments {
+            forward: true,
+            ..Default::default()
+        };
+
+        // 只有 12 字节的明文时没有 reduction, 必须从最后一个字节开始
+        let short = data(Attack::SIZE);
+        let zr = Zreduction::new(&short.keystream);
+        assert_eq!(
+            (Attack::SIZE - 1, Direction::Backward),
+            reduction_start(&forward, &short, &zr).unwrap()
+        );
+        let args = Arguments {
+            reduction_start: Some(0),
+            ..forward
+        };
+        assert_eq!(
+            Attack::SIZE - 1,
+            reduction_start(&args, &short, &zr).unwrap().0
+        );
+
+        let long = data(20);
+        let zr = Zreduction::new(&long.keystream);
+        assert_eq!(
+            (0, Direction::Forward),
+            reduction_start(&args, &long, &zr).unwrap()
+        );
+        let args = Arguments {
+            reduction_start: Some(5),
+            ..Default::default()
+        };
+        assert!(reduction_start(&args, &long, &zr).is_err());
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        let path = env::temp_dir().join(format!("rbkcrack_keys_test_{}.json", process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let keys: Keys = vec![0x8879dfed, 0x14335b6b, 0x8dc58b53]
+            .into_iter()
+            .collect();
+
+        let args = Arguments {
+            save_keys: Some(path.clone()),
+            ..Default::default()
+        };
+        report_keys(&args, std::slice::from_ref(&keys)).unwrap();
+
+        // 没有 -c 也能读回由密码得到的密钥
+        let mut args = Arguments {
+            keys: vec![path.clone()],
+            ..Default::default()
+        };
+        let loaded = load_keys(&mut args);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(vec![keys], loaded.unwrap());
+    }
+}
@@ -1,43 +1,87 @@
 use crate::utils::*;
-use lazy_static::lazy_static;
 
-lazy_static! {
-    pub static ref MULTTAB: MultTab = MultTab::new();
-}
+pub static MULTTAB: MultTab = MultTab::new();
 
 /// Lookup tables for multiplication related computations
 pub struct MultTab {
     // lookup tables
-    _multtab: [u32; 256],
     multinvtab: [u32; 256],
-    msbprodfiber2: Vec<Vec<u8>>, //[Vec<u8>; 256],
-    msbprodfiber3: Vec<Vec<u8>>, //[Vec<u8>; 256],
+    /// fibers stored one after another, the fiber of msbprodinv is
+    /// msbprodfiber2[msbprodfiber2offsets[msbprodinv]..msbprodfiber2offsets[msbprodinv + 1]]
+    msbprodfiber2: [u8; 2 * 256],
+    msbprodfiber2offsets: [u16; 257],
+    msbprodfiber3: [u8; 3 * 256],
+    msbprodfiber3offsets: [u16; 257],
 }
 
 impl MultTab {
     pub const MULT: u32 = 0x0808_8405;
     pub const MULTINV: u32 = 0xd94f_a8cd;
 
-    pub fn new() -> MultTab {
+    /// Compute the lookup tables, evaluated at compile time for `MULTTAB`
+    pub const fn new() -> MultTab {
         let mut multtab = MultTab {
-            _multtab: [0; 256],
             multinvtab: [0; 256],
-            msbprodfiber2: (0..256).map(|_| vec![0u8]).collect::<Vec<_>>(),
-            msbprodfiber3: (0..256).map(|_| vec![0u8]).collect::<Vec<_>>(),
+            msbprodfiber2: [0; 2 * 256],
+            msbprodfiber2offsets: [0; 257],
+            msbprodfiber3: [0; 3 * 256],
+            msbprodfiber3offsets: [0; 257],
         };
-        let mut prodinv = 0;
-        for x in 0..256 {
+
+        // first pass: compute mult^-1 * x and count the size of each fiber
+        let mut prodinv = 0u32;
+        let mut x = 0;
+        while x < 256 {
             multtab.multinvtab[x] = prodinv;
+            let m = msb(prodinv) as usize;
 
-            multtab.msbprodfiber2[msb(prodinv) as usize].push(x as u8);
-            multtab.msbprodfiber2[(msb(prodinv) as usize + 1) % 256].push(x as u8);
+            multtab.msbprodfiber2offsets[m + 1] += 1;
+            multtab.msbprodfiber2offsets[(m + 1) % 256 + 1] += 1;
 
-            multtab.msbprodfiber3[(msb(prodinv) as usize + 255) % 256].push(x as u8);
-            multtab.msbprodfiber3[msb(prodinv) as usize].push(x as u8);
-            multtab.msbprodfiber3[(msb(prodinv) as usize + 1) % 256].push(x as u8);
+            multtab.msbprodfiber3offsets[(m + 255) % 256 + 1] += 1;
+            multtab.msbprodfiber3offsets[m + 1] += 1;
+            multtab.msbprodfiber3offsets[(m + 1) % 256 + 1] += 1;
 
             prodinv = prodinv.wrapping_add(MultTab::MULTINV);
+            x += 1;
+        }
+
+        // turn the sizes into offsets
+        let mut i = 0;
+        while i < 256 {
+            multtab.msbprodfiber2offsets[i + 1] += multtab.msbprodfiber2offsets[i];
+            multtab.msbprodfiber3offsets[i + 1] += multtab.msbprodfiber3offsets[i];
+            i += 1;
         }
+
+        // second pass: fill the fibers, values are sorted in each fiber
+        let mut fill2 = [0u16; 256];
+        let mut fill3 = [0u16; 256];
+        let mut x = 0;
+        while x < 256 {
+            let m = msb(multtab.multinvtab[x]) as usize;
+
+            let mut d = 0;
+            while d < 2 {
+                let j = (m + d) % 256;
+                let pos = multtab.msbprodfiber2offsets[j] + fill2[j];
+                multtab.msbprodfiber2[pos as usize] = x as u8;
+                fill2[j] += 1;
+                d += 1;
+            }
+
+            let mut d = 0;
+            while d < 3 {
+                let j = (m + 255 + d) % 256;
+                let pos = multtab.msbprodfiber3offsets[j] + fill3[j];
+                multtab.msbprodfiber3[pos as usize] = x as u8;
+                fill3[j] += 1;
+                d += 1;
+            }
+
+            x += 1;
+        }
+
         multtab
     }
 
@@ -47,17 +91,65 @@ impl MultTab {
         self.multinvtab[x as usize]
     }
 
-    /// return a vector of bytes x such that
+    /// return a slice of bytes x such that
     /// msb(x*mult^-1) is equal to msbprod or msbprod-1
     #[inline]
-    pub fn get_msb_prod_fiber2(&self, msbprodinv: u8) -> &Vec<u8> {
-        &self.msbprodfiber2[msbprodinv as usize]
+    pub fn get_msb_prod_fiber2(&self, msbprodinv: u8) -> &[u8] {
+        let start = self.msbprodfiber2offsets[msbprodinv as usize] as usize;
+        let end = self.msbprodfiber2offsets[msbprodinv as usize + 1] as usize;
+        &self.msbprodfiber2[start..end]
     }
 
-    /// return a vector of bytes x such that
+    /// return a slice of bytes x such that
     /// msb(x*mult^-1) is equal to msbprod, msbprod-1 or msbprod+1
     #[inline]
-    pub fn get_msb_prod_fiber3(&self, msbprodinv: u8) -> &Vec<u8> {
-        &self.msbprodfiber3[msbprodinv as usize]
+    pub fn get_msb_prod_fiber3(&self, msbprodinv: u8) -> &[u8] {
+        let start = self.msbprodfiber3offsets[msbprodinv as usize] as usize;
+        let end = self.msbprodfiber3offsets[msbprodinv as usize + 1] as usize;
+        &self.msbprodfiber3[start..end]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MultTab, MULTTAB};
+    use crate::utils::*;
+    use alloc::vec::Vec;
+    use proptest::prelude::*;
+
+    /// bytes x such that msb(x*mult^-1) - msbprodinv is in the given differences
+    fn preimages(msbprodinv: u8, differences: &[u8]) -> Vec<u8> {
+        (0..=255)
+            .filter(|&x| {
+                let msbprod = msb(u32::from(x).wrapping_mul(MultTab::MULTINV));
+                differences.contains(&msbprod.wrapping_sub(msbprodinv))
+            })
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn get_multinv(x: u8) {
+            prop_assert_eq!(
+                u32::from(x).wrapping_mul(MultTab::MULTINV),
+                MULTTAB.get_multinv(x)
+            );
+        }
+
+        #[test]
+        fn get_msb_prod_fiber2(msbprodinv: u8) {
+            prop_assert_eq!(
+                preimages(msbprodinv, &[0, 0xff]),
+                MULTTAB.get_msb_prod_fiber2(msbprodinv)
+            );
+        }
+
+        #[test]
+        fn get_msb_prod_fiber3(msbprodinv: u8) {
+            prop_assert_eq!(
+                preimages(msbprodinv, &[0, 0xff, 1]),
+                MULTTAB.get_msb_prod_fiber3(msbprodinv)
+            );
+        }
     }
 }
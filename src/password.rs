@@ -0,0 +1,858 @@
+//! 由内部密钥反推密码
+//!
+//! 密码只用于初始化 `Keys`, 已知密钥后猜测密码只需对每个候选计算一次, 不再需要明文
+
+use crate::encoding::Encoding;
+use crate::keys::Keys;
+use failure::{format_err, Error};
+use rayon::prelude::*;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+/// 判断由候选密码得到的密钥是否正确
+pub trait Target: Sync {
+    fn is_match(&self, keys: &Keys) -> bool;
+
+    /// 去掉密码末尾的 suffix 后对应的目标密钥, 不知道目标密钥时返回 None
+    fn strip_suffix(&self, _suffix: &[u8]) -> Option<Vec<Keys>> {
+        None
+    }
+}
+
+/// 已知内部密钥时, 只需比较密钥
+impl Target for [Keys] {
+    fn is_match(&self, keys: &Keys) -> bool {
+        self.contains(keys)
+    }
+
+    fn strip_suffix(&self, suffix: &[u8]) -> Option<Vec<Keys>> {
+        let keys = self
+            .iter()
+            .map(|keys| {
+                let mut keys = keys.clone();
+                for &p in suffix.iter().rev() {
+                    keys.update_backword_plain(p);
+                }
+                keys
+            })
+            .collect();
+        Some(keys)
+    }
+}
+
+/// 只有密文时, 用各条目加密头的校验字节筛选密码, 通过后再由 confirm 确认
+///
+/// 每个条目的校验字节能排除 255/256 的错误密码
+pub struct HeaderCheck<F> {
+    headers: Vec<([u8; 12], u8)>,
+    confirm: F,
+}
+
+impl<F: Fn(&Keys) -> bool + Sync> HeaderCheck<F> {
+    /// headers 为各条目的加密头及其最后一个字节的期望值
+    pub fn new(headers: Vec<([u8; 12], u8)>, confirm: F) -> HeaderCheck<F> {
+        HeaderCheck { headers, confirm }
+    }
+}
+
+impl<F: Fn(&Keys) -> bool + Sync> Target for HeaderCheck<F> {
+    fn is_match(&self, keys: &Keys) -> bool {
+        self.headers.iter().all(|(header, check)| {
+            let mut header = *header;
+            keys.clone().decipher(&mut header);
+            header[11] == *check
+        }) && (self.confirm)(keys)
+    }
+}
+
+/// 每次读入并交给线程池的单词数
+const CHUNK_SIZE: usize = 1 << 12;
+
+/// leetspeak 替换表
+const LEET: [(u8, u8); 6] = [
+    (b'a', b'4'),
+    (b'e', b'3'),
+    (b'i', b'1'),
+    (b'o', b'0'),
+    (b's', b'5'),
+    (b't', b'7'),
+];
+
+/// 对字典中的单词做的变形
+///
+/// 写法为逗号分隔的 `case`, `leet` 和 `digits=N`, 如 `case,digits=2`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rules {
+    /// 尝试全小写, 全大写, 首字母大写及大小写互换
+    pub case: bool,
+    /// 尝试将字母替换为形似的数字
+    pub leet: bool,
+    /// 在末尾追加至多这么多位数字
+    pub digits: usize,
+}
+
+impl FromStr for Rules {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Rules, Error> {
+        let mut rules = Rules::default();
+        for rule in s.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            match rule {
+                "case" => rules.case = true,
+                "leet" => rules.leet = true,
+                "digits" => rules.digits = 2,
+                _ => match rule.strip_prefix("digits=") {
+                    Some(n) => {
+                        rules.digits = n
+                            .parse()
+                            .map_err(|_| format_err!("invalid number of digits '{}'", n))?
+                    }
+                    None => return Err(format_err!("unknown rule '{}'", rule)),
+                },
+            }
+        }
+        Ok(rules)
+    }
+}
+
+impl Rules {
+    /// 单词变形后 (不含追加的数字) 的所有写法, 第一个总是单词本身
+    pub fn stems(&self, word: &[u8]) -> Vec<Vec<u8>> {
+        let mut stems = vec![word.to_vec()];
+        if self.case {
+            let lower = word.to_ascii_lowercase();
+            let mut capitalized = lower.clone();
+            if let Some(first) = capitalized.first_mut() {
+                first.make_ascii_uppercase();
+            }
+            let swapped = word
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_lowercase() {
+                        b.to_ascii_uppercase()
+                    } else {
+                        b.to_ascii_lowercase()
+                    }
+                })
+                .collect();
+            stems.extend(vec![word.to_ascii_uppercase(), lower, capitalized, swapped]);
+        }
+        self.leet(stems)
+    }
+
+    /// 同 stems, 但大小写按 Unicode 变换, 再编码为 encodings 中的每种编码
+    ///
+    /// 无法编码的写法会被跳过
+    pub fn text_stems(&self, word: &str, encodings: &[Encoding]) -> Vec<Vec<u8>> {
+        let mut variants = vec![word.to_owned()];
+        if self.case {
+            let lower = word.to_lowercase();
+            let mut chars = lower.chars();
+            let capitalized = chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
+                .unwrap_or_default();
+            let swapped = word
+                .chars()
+                .flat_map(|c| {
+                    if c.is_lowercase() {
+                        c.to_uppercase().collect::<Vec<_>>()
+                    } else {
+                        c.to_lowercase().collect()
+                    }
+                })
+                .collect();
+            variants.extend(vec![word.to_uppercase(), lower, capitalized, swapped]);
+        }
+        let stems = encodings
+            .iter()
+            .flat_map(|encoding| variants.iter().filter_map(move |v| encoding.encode(v)))
+            .collect();
+        self.leet(stems)
+    }
+
+    /// 按规则加上 leetspeak 的写法并去重
+    fn leet(&self, mut stems: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        if self.leet {
+            let leet = stems
+                .iter()
+                .map(|stem| {
+                    stem.iter()
+                        .map(|&b| {
+                            LEET.iter()
+                                .find(|&&(c, _)| c == b.to_ascii_lowercase())
+                                .map_or(b, |&(_, d)| d)
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            stems.extend(leet);
+        }
+        dedup(stems)
+    }
+
+    /// 尝试单词的所有变形, 返回符合 target 的密码
+    pub fn search_word<T: Target + ?Sized>(&self, word: &[u8], target: &T) -> Option<Vec<u8>> {
+        self.search_stems(self.stems(word), target)
+    }
+
+    /// 尝试每个写法及在其后追加数字的结果
+    fn search_stems<T: Target + ?Sized>(&self, stems: Vec<Vec<u8>>, target: &T) -> Option<Vec<u8>> {
+        stems.into_iter().find_map(|mut stem| {
+            let keys = Keys::from_password(&stem);
+            if search_digits(&keys, &mut stem, self.digits, target) {
+                Some(stem)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// 保持顺序的去重
+fn dedup<T: PartialEq>(items: Vec<T>) -> Vec<T> {
+    let mut unique = Vec::with_capacity(items.len());
+    for item in items {
+        if !unique.contains(&item) {
+            unique.push(item);
+        }
+    }
+    unique
+}
+
+/// 在 password 后追加至多 depth 位数字, 找到时 password 即为密码
+///
+/// 后缀共享前缀的计算结果, 每个候选只需更新一个字节
+fn search_digits<T: Target + ?Sized>(
+    keys: &Keys,
+    password: &mut Vec<u8>,
+    depth: usize,
+    target: &T,
+) -> bool {
+    if target.is_match(keys) {
+        return true;
+    }
+    if depth == 0 {
+        return false;
+    }
+    for digit in b'0'..=b'9' {
+        let mut next = keys.clone();
+        next.update(digit);
+        password.push(digit);
+        if search_digits(&next, password, depth - 1, target) {
+            return true;
+        }
+        password.pop();
+    }
+    false
+}
+
+/// 字典的编码和密码的编码
+///
+/// 都未指定时单词按原始字节作为密码. 否则按 wordlist (默认 UTF-8) 解码单词,
+/// 按 Unicode 变换大小写后编码为 passwords (默认 UTF-8) 中的每种编码
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Encodings {
+    pub wordlist: Option<Encoding>,
+    pub passwords: Vec<Encoding>,
+}
+
+impl Encodings {
+    fn is_raw(&self) -> bool {
+        self.wordlist.is_none() && self.passwords.is_empty()
+    }
+
+    fn passwords(&self) -> &[Encoding] {
+        if self.passwords.is_empty() {
+            &[Encoding::Utf8]
+        } else {
+            &self.passwords
+        }
+    }
+}
+
+/// 读取一行, 含行尾的换行符
+///
+/// UTF-16 的换行符占两个字节, 其中的 0x0a 也可能是别的字符的一部分
+fn read_line<R: BufRead>(reader: &mut R, encoding: Option<Encoding>) -> io::Result<Vec<u8>> {
+    let mut line = vec![];
+    while reader.read_until(b'\n', &mut line)? != 0 {
+        match encoding {
+            Some(Encoding::Utf16Le) if line.len() % 2 == 1 => {
+                let mut high = [0];
+                if reader.read(&mut high)? == 0 {
+                    break;
+                }
+                line.push(high[0]);
+                if high[0] == 0 {
+                    break;
+                }
+            }
+            Some(Encoding::Utf16Le) => (),
+            Some(Encoding::Utf16Be) if line.len() % 2 == 0 && line[line.len() - 2] == 0 => break,
+            Some(Encoding::Utf16Be) => (),
+            _ => break,
+        }
+    }
+    Ok(line)
+}
+
+/// 逐行读取字典并尝试其中的单词, 返回找到的密码
+///
+/// 不转换编码时单词按字节处理, 不要求是 UTF-8; 每处理完一批会以已尝试的单词数调用 progress
+pub fn search_wordlist<R: BufRead, T: Target + ?Sized>(
+    mut reader: R,
+    rules: &Rules,
+    encodings: &Encodings,
+    target: &T,
+    mut progress: impl FnMut(usize),
+) -> Result<Option<Vec<u8>>, Error> {
+    if let Some(encoding) = encodings
+        .passwords
+        .iter()
+        .find(|e| !e.is_ascii_compatible())
+    {
+        return Err(format_err!("{} cannot be used for passwords", encoding));
+    }
+    let decoding = encodings.wordlist.unwrap_or(Encoding::Utf8);
+    let stems = |line: &Vec<u8>| {
+        if encodings.is_raw() {
+            // 去掉行尾的 \n 或 \r\n
+            let mut word = &line[..];
+            if word.last() == Some(&b'\n') {
+                word = &word[..word.len() - 1];
+                if word.last() == Some(&b'\r') {
+                    word = &word[..word.len() - 1];
+                }
+            }
+            rules.stems(word)
+        } else {
+            // 无法解码的行直接跳过, 字典开头可能有 BOM
+            decoding.decode(line).map_or_else(Vec::new, |text| {
+                let word = text.trim_end_matches(&['\r', '\n'][..]);
+                let word = word.trim_start_matches('\u{feff}');
+                rules.text_stems(word, encodings.passwords())
+            })
+        }
+    };
+
+    let mut done = 0;
+    let mut lines = Vec::with_capacity(CHUNK_SIZE);
+    loop {
+        lines.clear();
+        let mut eof = false;
+        while lines.len() < CHUNK_SIZE {
+            let line = read_line(&mut reader, encodings.wordlist)?;
+            if line.is_empty() {
+                eof = true;
+                break;
+            }
+            lines.push(line);
+        }
+
+        let found = lines
+            .par_iter()
+            .find_map_any(|line| rules.search_stems(stems(line), target));
+        done += lines.len();
+        progress(done);
+
+        if found.is_some() || eof {
+            return Ok(found);
+        }
+    }
+}
+
+/// 一个 mask 尝试的候选 (一个分块) 大致的数量, 每处理完一块报告一次进度
+const MASK_CHUNK_SIZE: u64 = 1 << 24;
+
+/// 每个并行任务枚举的候选数的上限
+const MASK_JOB_SIZE: u64 = 1 << 16;
+
+/// 一个位置的所有取值, 非 ASCII 字符编码后可能有多个字节
+type Charset = Vec<Vec<u8>>;
+
+/// 展开 hashcat 风格的字符集, 如 `?l?d_`
+///
+/// `?l` 小写字母, `?u` 大写字母, `?d` 数字, `?s` 符号 (含空格), `?a` 以上所有,
+/// `?e` 编码中 ASCII 以外的字母, `?b` 所有字节, `?1` 至 `?4` 为 custom 中的自定义字符集,
+/// `??` 为 `?` 本身; 其他字符按 encoding 编码
+fn parse_charset(charset: &str, custom: &[Charset], encoding: Encoding) -> Result<Charset, Error> {
+    let mut values = vec![];
+    let mut chars = charset.chars();
+    while let Some(c) = chars.next() {
+        if c != '?' {
+            values.push(encode_char(c, encoding)?);
+            continue;
+        }
+        match chars.next() {
+            Some(class) => values.extend(expand_class(class, custom, encoding)?),
+            None => return Err(format_err!("trailing '?' in '{}'", charset)),
+        }
+    }
+    Ok(dedup(values))
+}
+
+/// 按 encoding 编码单个字符
+fn encode_char(c: char, encoding: Encoding) -> Result<Vec<u8>, Error> {
+    encoding
+        .encode(c.encode_utf8(&mut [0; 4]))
+        .ok_or_else(|| format_err!("'{}' cannot be encoded in {}", c, encoding))
+}
+
+/// `?` 之后的字符对应的字符集
+fn expand_class(class: char, custom: &[Charset], encoding: Encoding) -> Result<Charset, Error> {
+    let lower = b'a'..=b'z';
+    let upper = b'A'..=b'Z';
+    let digits = b'0'..=b'9';
+    let symbols = (b' '..=b'~').filter(u8::is_ascii_punctuation);
+    let symbols = std::iter::once(b' ').chain(symbols);
+    let bytes: Vec<u8> = match class {
+        'l' => lower.collect(),
+        'u' => upper.collect(),
+        'd' => digits.collect(),
+        's' => symbols.collect(),
+        'a' => lower.chain(upper).chain(digits).chain(symbols).collect(),
+        'b' => (0..=255).collect(),
+        '?' => vec![b'?'],
+        'e' => {
+            return encoding
+                .letters()
+                .into_iter()
+                .map(|c| encode_char(c, encoding))
+                .collect()
+        }
+        '1'..='4' => {
+            return custom
+                .get(class as usize - '1' as usize)
+                .cloned()
+                .ok_or_else(|| format_err!("custom charset ?{} is not defined", class))
+        }
+        _ => return Err(format_err!("unknown charset ?{}", class)),
+    };
+    Ok(bytes.into_iter().map(|b| vec![b]).collect())
+}
+
+/// hashcat 风格的 mask, 每个位置对应一个字符集
+///
+/// 如 `Acme?a?a?d?d` 表示以 `Acme` 开头, 以两个数字结尾的 8 位密码
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mask {
+    charsets: Vec<Charset>,
+}
+
+impl Mask {
+    /// 解析 mask, custom 为 `?1` 至 `?4` 的定义, 非 ASCII 字符编码为 UTF-8
+    pub fn new(mask: &str, custom: &[&str]) -> Result<Mask, Error> {
+        Mask::with_encoding(mask, custom, Encoding::Utf8)
+    }
+
+    /// 同 new, 但非 ASCII 字符编码为 encoding
+    pub fn with_encoding(mask: &str, custom: &[&str], encoding: Encoding) -> Result<Mask, Error> {
+        if !encoding.is_ascii_compatible() {
+            return Err(format_err!("{} cannot be used for passwords", encoding));
+        }
+        let custom = custom
+            .iter()
+            .map(|charset| parse_charset(charset, &[], encoding))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut charsets = vec![];
+        let mut chars = mask.chars();
+        while let Some(c) = chars.next() {
+            let charset = match c {
+                '?' => {
+                    let class = chars
+                        .next()
+                        .ok_or_else(|| format_err!("trailing '?' in '{}'", mask))?;
+                    expand_class(class, &custom, encoding)?
+                }
+                _ => vec![encode_char(c, encoding)?],
+            };
+            if charset.is_empty() {
+                return Err(format_err!("empty charset in '{}'", mask));
+            }
+            charsets.push(charset);
+        }
+        Ok(Mask { charsets })
+    }
+
+    /// 密码的字符数
+    pub fn len(&self) -> usize {
+        self.charsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.charsets.is_empty()
+    }
+
+    /// 只保留前 len 个位置
+    pub fn prefix(&self, len: usize) -> Mask {
+        Mask {
+            charsets: self.charsets[..len.min(self.len())].to_vec(),
+        }
+    }
+
+    /// 候选密码的数量, 超出 u64 时返回 None
+    pub fn candidates(&self) -> Option<u64> {
+        self.charsets
+            .iter()
+            .try_fold(1u64, |n, charset| n.checked_mul(charset.len() as u64))
+    }
+
+    /// 尝试所有候选密码, 返回符合 target 的密码
+    ///
+    /// 开头固定的字符只从初始状态正向计算一次; 知道目标密钥时, 结尾固定的字符也只反向计算一次.
+    /// 每处理完一块会以 (已尝试的候选数, 候选总数) 调用 progress
+    pub fn search<T: Target + ?Sized>(
+        &self,
+        target: &T,
+        progress: impl FnMut(u64, u64),
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let total = self
+            .candidates()
+            .ok_or_else(|| format_err!("too many candidates in the mask"))?;
+
+        let fixed = |charset: &&Charset| charset.len() == 1;
+        let prefix_len = self.charsets.iter().take_while(fixed).count();
+        let suffix_len = self.charsets[prefix_len..]
+            .iter()
+            .rev()
+            .take_while(fixed)
+            .count();
+        let suffix = self.charsets[self.len() - suffix_len..].concat().concat();
+
+        let found = match target.strip_suffix(&suffix) {
+            Some(keys) => self.search_between(prefix_len, suffix_len, &keys[..], total, progress),
+            None => self.search_between(prefix_len, 0, target, total, progress),
+        };
+        Ok(found)
+    }
+
+    /// 枚举去掉开头 prefix_len 个和结尾 suffix_len 个固定字符后剩下的位置
+    fn search_between<T: Target + ?Sized>(
+        &self,
+        prefix_len: usize,
+        suffix_len: usize,
+        target: &T,
+        total: u64,
+        mut progress: impl FnMut(u64, u64),
+    ) -> Option<Vec<u8>> {
+        let prefix = self.charsets[..prefix_len].concat().concat();
+        let suffix = self.charsets[self.len() - suffix_len..].concat().concat();
+        let unknown = &self.charsets[prefix_len..self.len() - suffix_len];
+        let start = Keys::from_password(&prefix);
+
+        // 前 split 个位置的每种取值为一个任务, 任务内枚举剩下的位置
+        let mut split = 0;
+        let mut job_size = total;
+        while job_size > MASK_JOB_SIZE {
+            job_size /= unknown[split].len() as u64;
+            split += 1;
+        }
+        let (head, tail) = unknown.split_at(split);
+        let jobs = total / job_size;
+        let chunk = (MASK_CHUNK_SIZE / job_size).max(1);
+
+        let mut done = 0;
+        while done < jobs {
+            let end = jobs.min(done + chunk);
+            let found = (done..end).into_par_iter().find_map_any(|mut job| {
+                // 按混合进制解出前 split 个位置, 最后一个位置变化最快
+                let mut choices = vec![0; split];
+                for (choice, charset) in choices.iter_mut().zip(head).rev() {
+                    let n = charset.len() as u64;
+                    *choice = (job % n) as usize;
+                    job /= n;
+                }
+                let mut password = prefix.clone();
+                let mut keys = start.clone();
+                for (&choice, charset) in choices.iter().zip(head) {
+                    for &p in &charset[choice] {
+                        keys.update(p);
+                        password.push(p);
+                    }
+                }
+                if search_charsets(&keys, tail, &mut password, target) {
+                    password.extend_from_slice(&suffix);
+                    Some(password)
+                } else {
+                    None
+                }
+            });
+            done = end;
+            progress(done * job_size, total);
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+}
+
+/// 依次枚举 charsets 中各位置的字符, 找到时 password 即为密码
+fn search_charsets<T: Target + ?Sized>(
+    keys: &Keys,
+    charsets: &[Charset],
+    password: &mut Vec<u8>,
+    target: &T,
+) -> bool {
+    let (charset, rest) = match charsets.split_first() {
+        Some(split) => split,
+        None => return target.is_match(keys),
+    };
+    let len = password.len();
+    for value in charset {
+        let mut next = keys.clone();
+        for &p in value {
+            next.update(p);
+        }
+        password.extend_from_slice(value);
+        if search_charsets(&next, rest, password, target) {
+            return true;
+        }
+        password.truncate(len);
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{search_wordlist, Encodings, HeaderCheck, Mask, Rules};
+    use crate::encoding::Encoding;
+    use crate::keys::Keys;
+    use crate::keystream_tab::KEYSTREAMTAB;
+    use std::io::Cursor;
+
+    fn search(wordlist: &str, rules: &str, password: &[u8]) -> Option<Vec<u8>> {
+        let targets = [Keys::from_password(password)];
+        let rules = rules.parse::<Rules>().unwrap();
+        let encodings = Encodings::default();
+        search_wordlist(
+            Cursor::new(wordlist),
+            &rules,
+            &encodings,
+            &targets[..],
+            |_| (),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_rules() {
+        assert_eq!(Rules::default(), "".parse().unwrap());
+        assert_eq!(
+            Rules {
+                case: true,
+                leet: true,
+                digits: 3
+            },
+            "case, leet,digits=3".parse().unwrap()
+        );
+        assert_eq!(2, "digits".parse::<Rules>().unwrap().digits);
+        assert!("digits=x".parse::<Rules>().is_err());
+        assert!("reverse".parse::<Rules>().is_err());
+    }
+
+    #[test]
+    fn stems() {
+        let rules = Rules {
+            case: true,
+            leet: true,
+            digits: 0,
+        };
+        let stems = rules.stems(b"pAss");
+        assert_eq!(b"pAss", &stems[0][..]);
+        for stem in &["PASS", "pass", "Pass", "PaSS", "p455", "P455"] {
+            assert!(stems.contains(&stem.as_bytes().to_vec()), "{}", stem);
+        }
+        assert_eq!(vec![b"".to_vec()], rules.stems(b""));
+    }
+
+    #[test]
+    fn wordlist() {
+        let wordlist = "foo\r\nsecret\nbar";
+        assert_eq!(Some(b"secret".to_vec()), search(wordlist, "", b"secret"));
+        assert_eq!(Some(b"bar".to_vec()), search(wordlist, "", b"bar"));
+        assert_eq!(None, search(wordlist, "", b"Secret"));
+        assert_eq!(
+            Some(b"Secret42".to_vec()),
+            search(wordlist, "case,digits=2", b"Secret42")
+        );
+        assert_eq!(
+            Some(b"53cr37".to_vec()),
+            search(wordlist, "leet", b"53cr37")
+        );
+        assert_eq!(None, search(wordlist, "case,leet,digits=1", b"Secret42"));
+    }
+
+    #[test]
+    fn encoded_wordlist() {
+        let search = |wordlist: &[u8], rules: &str, encodings: &Encodings, password: &[u8]| {
+            let targets = [Keys::from_password(password)];
+            let rules = rules.parse::<Rules>().unwrap();
+            search_wordlist(
+                Cursor::new(wordlist),
+                &rules,
+                encodings,
+                &targets[..],
+                |_| (),
+            )
+            .unwrap()
+        };
+        let cp437 = Encodings {
+            wordlist: None,
+            passwords: vec![Encoding::Utf8, Encoding::Cp437],
+        };
+        let wordlist = "foo\ngrüße\n".as_bytes();
+        assert_eq!(
+            Some(b"GR\x9aSSE".to_vec()),
+            search(wordlist, "case", &cp437, b"GR\x9aSSE")
+        );
+        assert_eq!(
+            Some(b"Gr\x81\xe1e1".to_vec()),
+            search(wordlist, "case,digits=1", &cp437, b"Gr\x81\xe1e1")
+        );
+        assert_eq!(
+            None,
+            search(wordlist, "", &Encodings::default(), b"Gr\x81\xe1e")
+        );
+
+        // UTF-16 的字典, 其中 U+0A0A 含有 0x0a 字节
+        let utf16 = |encoding: Encoding| Encodings {
+            wordlist: Some(encoding),
+            passwords: vec![],
+        };
+        let text = "\u{feff}foo\r\n\u{a0a}x\nпароль\n";
+        let le = Encoding::Utf16Le.encode(text).unwrap();
+        let be = Encoding::Utf16Be.encode(text).unwrap();
+        for &(wordlist, encoding) in &[(&le, Encoding::Utf16Le), (&be, Encoding::Utf16Be)] {
+            for password in &["foo", "\u{a0a}x", "пароль"] {
+                assert_eq!(
+                    Some(password.as_bytes().to_vec()),
+                    search(wordlist, "", &utf16(encoding), password.as_bytes())
+                );
+            }
+        }
+        let passwords = Encodings {
+            wordlist: None,
+            passwords: vec![Encoding::Utf16Le],
+        };
+        let targets = [Keys::from_password(b"foo")];
+        let result = search_wordlist(
+            Cursor::new("foo"),
+            &Rules::default(),
+            &passwords,
+            &targets[..],
+            |_| (),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_mask() {
+        let mask = Mask::new("a?d??", &[]).unwrap();
+        assert_eq!(3, mask.len());
+        assert_eq!(Some(10), mask.candidates());
+        assert_eq!(Some(95), Mask::new("?a", &[]).unwrap().candidates());
+        assert_eq!(Some(33), Mask::new("?s", &[]).unwrap().candidates());
+        assert_eq!(
+            Some(36 * 16),
+            Mask::new("?1?2", &["?l?d", "?dabcdef"])
+                .unwrap()
+                .candidates()
+        );
+        assert_eq!(Some(1), Mask::new("?1", &["aaa"]).unwrap().candidates());
+        assert_eq!(
+            Some(1),
+            Mask::new("?a?a?a", &[]).unwrap().prefix(0).candidates()
+        );
+        assert_eq!(None, Mask::new(&"?b".repeat(9), &[]).unwrap().candidates());
+        assert!(Mask::new("?1", &[]).is_err());
+        assert!(Mask::new("?x", &[]).is_err());
+        assert!(Mask::new("abc?", &[]).is_err());
+        assert!(Mask::new("?1", &[""]).is_err());
+
+        let mask = Mask::new("ü?1", &["äö?d"]).unwrap();
+        assert_eq!(2, mask.len());
+        assert_eq!(Some(12), mask.candidates());
+        assert!(Mask::with_encoding("€", &[], Encoding::Cp437).is_err());
+        assert!(Mask::with_encoding("a", &[], Encoding::Utf16Le).is_err());
+        let letters = Encoding::Cp1252.letters().len() as u64;
+        assert_eq!(
+            Some(letters),
+            Mask::with_encoding("?e", &[], Encoding::Cp1252)
+                .unwrap()
+                .candidates()
+        );
+    }
+
+    #[test]
+    fn mask() {
+        let search = |mask: &str, password: &[u8]| {
+            let mask = Mask::new(mask, &["xyz"]).unwrap();
+            let mut last = (0, 0);
+            let found = mask
+                .search(&[Keys::from_password(password)][..], |done, total| {
+                    last = (done, total)
+                })
+                .unwrap();
+            (found, last)
+        };
+        assert_eq!(
+            (Some(b"Acme7x42".to_vec()), (3000, 3000)),
+            search("Acme?d?1?d?d", b"Acme7x42")
+        );
+        assert_eq!(
+            (Some(b"4-2Acme".to_vec()), (100, 100)),
+            search("?d-?dAcme", b"4-2Acme")
+        );
+        assert_eq!((Some(b"ab!".to_vec()), (1, 1)), search("ab!", b"ab!"));
+        assert_eq!(Some(b"!Zq#".to_vec()), search("?s?u?l?s", b"!Zq#").0);
+        assert_eq!((None, (26 * 26, 26 * 26)), search("?l?l", b"abc"));
+
+        let search = |mask: &str, encoding: Encoding, password: &[u8]| {
+            let mask = Mask::with_encoding(mask, &["äöü"], encoding).unwrap();
+            let targets = [Keys::from_password(password)];
+            mask.search(&targets[..], |_, _| ()).unwrap()
+        };
+        let password = "Grüße".as_bytes();
+        assert_eq!(
+            Some(password.to_vec()),
+            search("Gr?1?e?l", Encoding::Utf8, password)
+        );
+        let upper = "GrüßE".as_bytes();
+        assert_eq!(
+            Some(upper.to_vec()),
+            search("?u?l?e?eE", Encoding::Utf8, upper)
+        );
+        assert_eq!(
+            Some(b"Gr\x81\xe1e".to_vec()),
+            search("Gr?1?e?l", Encoding::Cp437, b"Gr\x81\xe1e")
+        );
+        assert_eq!(None, search("Gr?1?e?l", Encoding::Cp437, password));
+    }
+
+    #[test]
+    fn header_check() {
+        // 用密码加密几个以校验字节结尾的加密头
+        let headers = (0..4u8)
+            .map(|i| {
+                let mut keys = Keys::from_password(b"pw7");
+                let header = (0..12u8).map(|j| i * 31 + j * 7).collect::<Vec<_>>();
+                let mut cipher = [0; 12];
+                for (c, &p) in cipher.iter_mut().zip(&header) {
+                    *c = p ^ KEYSTREAMTAB.get_byte(keys.get_z());
+                    keys.update(p);
+                }
+                (cipher, header[11])
+            })
+            .collect::<Vec<_>>();
+
+        let target = HeaderCheck::new(headers.clone(), |_: &Keys| true);
+        let found = Mask::new("?l?l?d", &[]).unwrap().search(&target, |_, _| ());
+        assert_eq!(Some(b"pw7".to_vec()), found.unwrap());
+
+        let target = HeaderCheck::new(headers, |keys: &Keys| *keys != Keys::from_password(b"pw7"));
+        let found = Mask::new("pw?d", &[]).unwrap().search(&target, |_, _| ());
+        assert_eq!(None, found.unwrap());
+    }
+}
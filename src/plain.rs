@@ -0,0 +1,457 @@
+use crate::file::Bytes;
+use failure::{format_err, Error};
+use lazy_static::lazy_static;
+use std::fs;
+use std::io::{stdin, Read};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+lazy_static! {
+    /// 标准输入只能读取一次, 读取后缓存起来供攻击和解密共用
+    static ref STDIN: Mutex<Option<Bytes>> = Mutex::new(None);
+}
+
+/// 一段已知明文
+#[derive(Debug, Clone)]
+pub struct Fragment {
+    /// 相对于去掉加密头的密文的偏移 (可以为负)
+    pub offset: i32,
+    pub data: Bytes,
+}
+
+/// 解析命令行中直接给出的明文
+///
+/// 支持 `hex:50 4b 03 04`, `text:<?xml\x20` 以及 `base64:UEsDBA==` 三种写法,
+/// 不是这三种写法时返回 None
+pub fn parse_literal(literal: &str) -> Result<Option<Vec<u8>>, Error> {
+    let data = if let Some(hex) = literal.strip_prefix("hex:") {
+        parse_hex(hex)?
+    } else if let Some(text) = literal.strip_prefix("text:") {
+        unescape(text)?
+    } else if let Some(base64) = literal.strip_prefix("base64:") {
+        decode_base64(base64)?
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(data))
+}
+
+/// 解析十六进制字符串, 忽略其中的空白
+fn parse_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    let digits = hex
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| {
+            c.to_digit(16)
+                .map(|d| d as u8)
+                .ok_or_else(|| format_err!("invalid hex digit '{}'", c))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if digits.len() % 2 != 0 {
+        return Err(format_err!("odd number of hex digits"));
+    }
+    Ok(digits.chunks(2).map(|d| d[0] << 4 | d[1]).collect())
+}
+
+/// 处理 `\n`, `\r`, `\t`, `\0`, `\\` 和 `\xHH` 转义
+fn unescape(text: &str) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            data.push(b);
+            continue;
+        }
+        let escaped = match bytes.next() {
+            Some(b'n') => b'\n',
+            Some(b'r') => b'\r',
+            Some(b't') => b'\t',
+            Some(b'0') => b'\0',
+            Some(b'\\') => b'\\',
+            Some(b'x') => {
+                let hex = bytes.by_ref().take(2).collect::<Vec<_>>();
+                // 必须正好是两个十六进制数字, 不能含有空白
+                if hex.len() != 2 || !hex.iter().all(u8::is_ascii_hexdigit) {
+                    let hex = String::from_utf8_lossy(&hex).into_owned();
+                    return Err(format_err!("invalid \\x escape \\x{}", hex));
+                }
+                u8::from_str_radix(std::str::from_utf8(&hex)?, 16)?
+            }
+            Some(c) => return Err(format_err!("unknown escape sequence \\{}", c as char)),
+            None => return Err(format_err!("trailing backslash")),
+        };
+        data.push(escaped);
+    }
+    Ok(data)
+}
+
+/// 解码 base64, 忽略其中的空白
+fn decode_base64(base64: &str) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::with_capacity(base64.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in base64.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => return Err(format_err!("invalid base64 character '{}'", c as char)),
+        };
+        acc = acc << 6 | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((acc >> bits) as u8);
+        }
+    }
+    // 最后一组中凑不满一个字节的位必须为 0, 单独一个字符则连一个字节都凑不满
+    if bits >= 6 || acc & ((1 << bits) - 1) != 0 {
+        return Err(format_err!("invalid base64 length or trailing bits"));
+    }
+    Ok(data)
+}
+
+/// 读取列出了多段明文及其偏移的文件, `-` 表示标准输入
+///
+/// 每行一段明文, 格式为 `偏移 明文`, 明文的写法同 [`parse_literal`],
+/// 空行以及以 `#` 开头的行会被忽略
+pub fn read_spec_file(path: &str) -> Result<Vec<Fragment>, Error> {
+    let text = if path == "-" {
+        read_stdin()?
+    } else {
+        Bytes::from(fs::read(path)?)
+    };
+    let mut fragments = Vec::new();
+    for (i, line) in String::from_utf8_lossy(&text).lines().enumerate() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let err = |msg: &str| format_err!("{}:{}: {}", path, i + 1, msg);
+        let mut parts = line.splitn(2, char::is_whitespace);
+        let offset = parts
+            .next()
+            .and_then(|offset| offset.parse().ok())
+            .ok_or_else(|| err("invalid offset"))?;
+        let literal = parts.next().map(str::trim_start).unwrap_or("");
+        let data = parse_literal(literal)
+            .map_err(|e| err(&e.to_string()))?
+            .ok_or_else(|| err("plaintext must start with hex:, text: or base64:"))?;
+        fragments.push(Fragment {
+            offset,
+            data: Bytes::from(data),
+        });
+    }
+    Ok(fragments)
+}
+
+/// 嵌套在加密条目中的压缩包的第一个本地文件头
+///
+/// 未存储 (stored) 的 zip, jar, docx, apk 等压缩包开头的本地文件头大部分是可以预测的,
+/// 为 None 的字段视为未知, 时间, CRC32 和大小一般也是未知的
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NestedHeader {
+    /// 内层第一个条目的名字
+    pub name: Option<String>,
+    pub version: Option<u16>,
+    pub flags: Option<u16>,
+    pub method: Option<u16>,
+    /// extra field 的内容, 同时决定了其长度
+    pub extra: Option<Vec<u8>>,
+    /// 已知的条目内容, 只对 stored 的条目有意义
+    pub content: Option<Vec<u8>>,
+}
+
+impl NestedHeader {
+    /// 根据外层条目的扩展名猜测内层压缩包的第一个条目
+    pub fn guess(outer_name: &str) -> NestedHeader {
+        let file_name = outer_name.rsplit('/').next().unwrap_or(outer_name);
+        let (stem, ext) = match file_name.rfind('.') {
+            Some(i) if i > 0 => (&file_name[..i], file_name[i + 1..].to_ascii_lowercase()),
+            _ => return NestedHeader::default(),
+        };
+        let opendocument = |kind: &str| NestedHeader {
+            name: Some("mimetype".into()),
+            method: Some(0),
+            extra: Some(vec![]),
+            content: Some(kind.as_bytes().to_vec()),
+            ..Default::default()
+        };
+        match ext.as_str() {
+            // Microsoft Office
+            "docx" | "docm" | "dotx" | "xlsx" | "xlsm" | "pptx" | "pptm" | "vsdx" => NestedHeader {
+                name: Some("[Content_Types].xml".into()),
+                version: Some(20),
+                flags: Some(0x0006),
+                method: Some(8),
+                extra: Some(vec![]),
+                ..Default::default()
+            },
+            // jar 命令生成的目录项带有 0xcafe 标记
+            "jar" | "war" | "ear" => NestedHeader {
+                name: Some("META-INF/".into()),
+                version: Some(20),
+                flags: Some(0x0808),
+                method: Some(8),
+                extra: Some(vec![0xfe, 0xca, 0x00, 0x00]),
+                ..Default::default()
+            },
+            "apk" => NestedHeader {
+                name: Some("AndroidManifest.xml".into()),
+                method: Some(8),
+                extra: Some(vec![]),
+                ..Default::default()
+            },
+            "odt" => opendocument("application/vnd.oasis.opendocument.text"),
+            "ods" => opendocument("application/vnd.oasis.opendocument.spreadsheet"),
+            "odp" => opendocument("application/vnd.oasis.opendocument.presentation"),
+            "odg" => opendocument("application/vnd.oasis.opendocument.graphics"),
+            "epub" => opendocument("application/epub+zip"),
+            // 打包整个目录时第一个条目往往就是这个目录
+            "zip" if !stem.is_empty() => NestedHeader {
+                name: Some(format!("{}/", stem)),
+                method: Some(0),
+                ..Default::default()
+            },
+            _ => NestedHeader::default(),
+        }
+    }
+
+    /// 未知的字段由 other 补充
+    pub fn or(self, other: NestedHeader) -> NestedHeader {
+        NestedHeader {
+            name: self.name.or(other.name),
+            version: self.version.or(other.version),
+            flags: self.flags.or(other.flags),
+            method: self.method.or(other.method),
+            extra: self.extra.or(other.extra),
+            content: self.content.or(other.content),
+        }
+    }
+
+    /// 生成本地文件头中已知的部分, 偏移相对于内层压缩包的开头
+    pub fn fragments(&self) -> Result<Vec<Fragment>, Error> {
+        let name = self.name.as_ref().ok_or_else(|| {
+            format_err!("cannot guess the name of the first entry of the nested archive")
+        })?;
+        if name.is_empty() || name.len() > 0xffff {
+            return Err(format_err!("invalid nested entry name '{}'", name));
+        }
+
+        let mut header = vec![None; 30];
+        put(&mut header, 0, b"PK\x03\x04");
+        if let Some(version) = self.version {
+            put(&mut header, 4, &version.to_le_bytes());
+        }
+        if let Some(flags) = self.flags {
+            put(&mut header, 6, &flags.to_le_bytes());
+        }
+        if let Some(method) = self.method {
+            put(&mut header, 8, &method.to_le_bytes());
+        }
+        // 目录以及带有 data descriptor 的条目在本地文件头中的 CRC32 和大小都是 0
+        if name.ends_with('/') || self.flags.is_some_and(|flags| flags & 0x8 != 0) {
+            put(&mut header, 14, &[0; 12]);
+        }
+        put(&mut header, 26, &(name.len() as u16).to_le_bytes());
+        put(&mut header, 30, name.as_bytes());
+        // extra field 的长度未知时后面的内容也就无从定位了
+        if let Some(extra) = &self.extra {
+            if extra.len() > 0xffff {
+                return Err(format_err!("nested extra field is too large"));
+            }
+            put(&mut header, 28, &(extra.len() as u16).to_le_bytes());
+            put(&mut header, 30 + name.len(), extra);
+            if let Some(content) = &self.content {
+                put(&mut header, 30 + name.len() + extra.len(), content);
+            }
+        }
+
+        // 连续的已知字节组成一段明文
+        let mut fragments = Vec::new();
+        let mut i = 0;
+        while i < header.len() {
+            if header[i].is_none() {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < header.len() && header[i].is_some() {
+                i += 1;
+            }
+            fragments.push(Fragment {
+                offset: start as i32,
+                data: Bytes::from(
+                    header[start..i]
+                        .iter()
+                        .map(|b| b.unwrap())
+                        .collect::<Vec<_>>(),
+                ),
+            });
+        }
+        Ok(fragments)
+    }
+}
+
+/// 在 offset 处填入已知的字节
+fn put(header: &mut Vec<Option<u8>>, offset: usize, bytes: &[u8]) {
+    if header.len() < offset + bytes.len() {
+        header.resize(offset + bytes.len(), None);
+    }
+    for (i, &b) in bytes.iter().enumerate() {
+        header[offset + i] = Some(b);
+    }
+}
+
+/// 解析 `--nested` 的参数
+///
+/// `auto` 表示全部根据外层条目的名字猜测, 否则为逗号分隔的 `name=...`, `version=N`, `flags=N`,
+/// `method=stored|deflate|N` 和 `extra=HEX`, 没有给出的字段仍然会去猜测
+impl FromStr for NestedHeader {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<NestedHeader, Error> {
+        let mut header = NestedHeader::default();
+        if s == "auto" {
+            return Ok(header);
+        }
+        let number = |value: &str| {
+            let parsed = match value.strip_prefix("0x") {
+                Some(hex) => u16::from_str_radix(hex, 16),
+                None => value.parse(),
+            };
+            parsed.map_err(|_| format_err!("invalid number '{}'", value))
+        };
+        for field in s.split(',') {
+            let mut parts = field.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let value = parts
+                .next()
+                .ok_or_else(|| format_err!("expected key=value in '{}'", field))?;
+            match key {
+                "name" => header.name = Some(value.to_string()),
+                "version" => header.version = Some(number(value)?),
+                "flags" => header.flags = Some(number(value)?),
+                "method" => {
+                    header.method = Some(match value {
+                        "stored" => 0,
+                        "deflate" => 8,
+                        _ => number(value)?,
+                    })
+                }
+                "extra" => header.extra = Some(parse_hex(value)?),
+                _ => return Err(format_err!("unknown nested header field '{}'", key)),
+            }
+        }
+        Ok(header)
+    }
+}
+
+/// 读取标准输入中的全部数据
+pub fn read_stdin() -> Result<Bytes, Error> {
+    let mut cache = STDIN.lock().unwrap();
+    if cache.is_none() {
+        let mut data = Vec::new();
+        stdin().read_to_end(&mut data)?;
+        *cache = Some(Bytes::from(data));
+    }
+    Ok(cache.clone().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_literal, NestedHeader};
+
+    #[test]
+    fn literal() {
+        let expected = Some(b"PK\x03\x04".to_vec());
+        assert_eq!(expected, parse_literal("hex:504b0304").unwrap());
+        assert_eq!(expected, parse_literal("hex:50 4B 03 04").unwrap());
+        assert_eq!(expected, parse_literal("text:PK\\x03\\x04").unwrap());
+        assert_eq!(expected, parse_literal("base64:UEsDBA==").unwrap());
+        assert_eq!(expected, parse_literal("base64:UEsD BA").unwrap());
+        assert_eq!(
+            Some(b"a\tb\\\n".to_vec()),
+            parse_literal("text:a\\tb\\\\\\n").unwrap()
+        );
+        assert_eq!(None, parse_literal("plain.txt").unwrap());
+        assert!(parse_literal("hex:504").is_err());
+        assert!(parse_literal("text:\\q").is_err());
+        assert!(parse_literal("text:\\x  ").is_err());
+        assert!(parse_literal("text:\\xg1").is_err());
+        assert!(parse_literal("text:\\x4").is_err());
+        assert!(parse_literal("base64:UEsDB").is_err());
+        assert!(parse_literal("base64:UEsDBB==").is_err());
+    }
+
+    #[test]
+    fn nested() {
+        let fragments = |header: NestedHeader| {
+            header
+                .fragments()
+                .unwrap()
+                .into_iter()
+                .map(|f| (f.offset, f.data.to_vec()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            vec![
+                (0, b"PK\x03\x04\x14\x00\x06\x00\x08\x00".to_vec()),
+                (26, b"\x13\x00\x00\x00[Content_Types].xml".to_vec()),
+            ],
+            fragments(NestedHeader::guess("reports/Q3.DOCX"))
+        );
+        let mut jar = vec![0; 12];
+        jar.extend(b"\x09\x00\x04\x00META-INF/\xfe\xca\x00\x00");
+        assert_eq!(
+            vec![
+                (0, b"PK\x03\x04\x14\x00\x08\x08\x08\x00".to_vec()),
+                (14, jar),
+            ],
+            fragments(NestedHeader::guess("app.jar"))
+        );
+        let epub = fragments(NestedHeader::guess("book.epub"));
+        assert_eq!(
+            (26, b"\x08\x00\x00\x00mimetypeapplication/epub+zip".to_vec()),
+            epub[2]
+        );
+        let mut dir = vec![0; 12];
+        dir.extend(b"\x07\x00");
+        assert_eq!(
+            vec![
+                (0, b"PK\x03\x04".to_vec()),
+                (8, vec![0, 0]),
+                (14, dir),
+                (30, b"backup/".to_vec()),
+            ],
+            fragments(NestedHeader::guess("backup.zip"))
+        );
+        assert!(NestedHeader::guess("data.bin").fragments().is_err());
+
+        let header: NestedHeader = "name=a.txt,method=stored,extra=".parse().unwrap();
+        assert_eq!(
+            vec![
+                (0, b"PK\x03\x04".to_vec()),
+                (8, vec![0, 0]),
+                (26, b"\x05\x00\x00\x00a.txt".to_vec()),
+            ],
+            fragments(header)
+        );
+        let header = "flags=0x0800,version=10"
+            .parse::<NestedHeader>()
+            .unwrap()
+            .or(NestedHeader::guess("Q3.docx"));
+        assert_eq!(
+            (Some(0x0800), Some(10), Some(8)),
+            (header.flags, header.version, header.method)
+        );
+        assert_eq!(NestedHeader::default(), "auto".parse().unwrap());
+        assert!("name".parse::<NestedHeader>().is_err());
+        assert!("size=1".parse::<NestedHeader>().is_err());
+        assert!("version=x".parse::<NestedHeader>().is_err());
+        assert!("extra=fec".parse::<NestedHeader>().is_err());
+    }
+}
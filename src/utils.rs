@@ -1,14 +1,15 @@
 #[inline]
-pub fn lsb(x: u32) -> u8 {
+pub const fn lsb(x: u32) -> u8 {
     x as u8
 }
 
 #[inline]
-pub fn msb(x: u32) -> u8 {
+pub const fn msb(x: u32) -> u8 {
     (x >> 24) as u8
 }
 
 pub const MASK_0_16: u32 = 0x0000_ffff;
+pub const MASK_2_16: u32 = 0x0000_fffc;
 pub const MASK_26_32: u32 = 0xfc00_0000;
 pub const MASK_24_32: u32 = 0xff00_0000;
 pub const MASK_10_32: u32 = 0xffff_fc00;
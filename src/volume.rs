@@ -0,0 +1,231 @@
+use failure::{format_err, Error};
+use log::debug;
+use podio::ReadPodExt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// PKZIP 分卷压缩包的第一个分卷以此开头
+pub const SPLIT_MARKER: [u8; 4] = *b"PK\x07\x08";
+/// 原本准备分卷, 最后只写了一个文件时使用的标记
+pub const SINGLE_SEGMENT_MARKER: [u8; 4] = *b"PK00";
+
+/// 将分卷压缩包的各个分卷拼接为一个可 seek 的流
+///
+/// 支持 PKZIP 式的 `.z01`, `.z02`, ..., `.zip` 以及 7-Zip 式的 `.001`, `.002`, ...
+/// 单个文件视为只有一个分卷
+pub struct MultiVolume {
+    volumes: Vec<File>,
+    /// 每个分卷在流中的起始位置
+    starts: Vec<u64>,
+    len: u64,
+    pos: u64,
+}
+
+impl MultiVolume {
+    /// 打开 path 所属的分卷组
+    pub fn open(path: &str) -> Result<MultiVolume, Error> {
+        let paths = volume_paths(Path::new(path))?;
+        debug!("volumes: {:?}", paths);
+        let mut reader = MultiVolume::from_paths(&paths)?;
+        // 7-Zip 式的分卷是直接切分的, 没有标记
+        if split_marker_len(&mut reader)? != 0 {
+            debug!("found a split marker at the start of {:?}", paths[0]);
+        }
+        Ok(reader)
+    }
+
+    /// 按顺序拼接给定的文件
+    pub fn from_paths<P: AsRef<Path>>(paths: &[P]) -> Result<MultiVolume, Error> {
+        let mut volumes = Vec::with_capacity(paths.len());
+        let mut starts = Vec::with_capacity(paths.len());
+        let mut len = 0;
+        for path in paths {
+            let file = File::open(path)?;
+            starts.push(len);
+            len += file.metadata()?.len();
+            volumes.push(file);
+        }
+        Ok(MultiVolume {
+            volumes,
+            starts,
+            len,
+            pos: 0,
+        })
+    }
+
+    /// 每个分卷在流中的起始位置, 用于换算 central directory 中按分卷记录的偏移
+    pub fn volume_starts(&self) -> &[u64] {
+        &self.starts
+    }
+
+    /// 若 [start, start + len) 完全位于一个分卷中, 返回该分卷以及 start 在其中的偏移
+    pub fn volume_of(&self, start: u64, len: u64) -> Option<(&File, u64)> {
+        let i = self.starts.iter().rposition(|&s| s <= start)?;
+        let end = self.starts.get(i + 1).cloned().unwrap_or(self.len);
+        if start.checked_add(len)? <= end {
+            Some((&self.volumes[i], start - self.starts[i]))
+        } else {
+            None
+        }
+    }
+
+    /// 流的总长度
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Read for MultiVolume {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        // 找到 pos 所在的分卷, 跳过空的分卷
+        let i = match self.starts.binary_search(&self.pos) {
+            Ok(i) => self
+                .starts
+                .iter()
+                .rposition(|&s| s == self.starts[i])
+                .unwrap(),
+            Err(i) => i - 1,
+        };
+        let end = self.starts.get(i + 1).cloned().unwrap_or(self.len);
+        let to_read = buf.len().min((end - self.pos) as usize);
+
+        let volume = &mut self.volumes[i];
+        volume.seek(SeekFrom::Start(self.pos - self.starts[i]))?;
+        let n = volume.read(&mut buf[..to_read])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for MultiVolume {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => offset(self.len, n),
+            SeekFrom::Current(n) => offset(self.pos, n),
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            )),
+        }
+    }
+}
+
+/// 流开头的分卷标记的长度, 没有标记时为 0
+///
+/// 标记不属于任何条目, 但 central directory 中第一个分卷的偏移是把它算在内的
+pub fn split_marker_len<R: ReadPodExt + Seek>(reader: &mut R) -> io::Result<u64> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    if len < SPLIT_MARKER.len() as u64 {
+        return Ok(0);
+    }
+    let marker = reader.read_exact(SPLIT_MARKER.len())?;
+    reader.seek(SeekFrom::Start(0))?;
+    if marker == SPLIT_MARKER || marker == SINGLE_SEGMENT_MARKER {
+        Ok(marker.len() as u64)
+    } else {
+        Ok(0)
+    }
+}
+
+fn offset(base: u64, n: i64) -> Option<u64> {
+    if n >= 0 {
+        base.checked_add(n as u64)
+    } else {
+        base.checked_sub(n.wrapping_neg() as u64)
+    }
+}
+
+/// 根据文件名找出同一组的所有分卷
+fn volume_paths(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_owned();
+    let numbered = |prefix: &str, width: usize, first: usize| {
+        (first..)
+            .map(|n| path.with_extension(format!("{}{:0width$}", prefix, n, width = width)))
+            .take_while(|p| p.exists())
+            .collect::<Vec<_>>()
+    };
+
+    if ext.len() == 3 && ext.chars().all(|c| c.is_ascii_digit()) {
+        // 7-Zip 式: 直接切分的 .001, .002, ...
+        let paths = numbered("", 3, 1);
+        if paths.is_empty() {
+            return Err(format_err!("could not find the first volume of {:?}", path));
+        }
+        Ok(paths)
+    } else if is_pkzip_split(&ext) || ext.eq_ignore_ascii_case("zip") {
+        // PKZIP 式: .z01, .z02, ..., 最后一个分卷是 .zip
+        let upper = ext.starts_with(|c: char| c.is_ascii_uppercase());
+        let (prefix, last) = if upper { ("Z", "ZIP") } else { ("z", "zip") };
+        let last = path.with_extension(last);
+        let mut paths = numbered(prefix, 2, 1);
+        if paths.is_empty() && ext.eq_ignore_ascii_case("zip") {
+            return Ok(vec![path.to_owned()]);
+        }
+        if !last.exists() {
+            return Err(format_err!("missing the last volume {:?}", last));
+        }
+        paths.push(last);
+        Ok(paths)
+    } else {
+        Ok(vec![path.to_owned()])
+    }
+}
+
+fn is_pkzip_split(ext: &str) -> bool {
+    ext.len() >= 3 && ext.starts_with(['z', 'Z']) && ext[1..].chars().all(|c| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MultiVolume;
+    use podio::ReadPodExt;
+    use std::fs;
+    use std::io::{Seek, SeekFrom};
+    use std::process;
+
+    #[test]
+    fn read_across_volumes() {
+        let dir = std::env::temp_dir().join(format!("rbkcrack_volume_test_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths = (0..3)
+            .map(|i| dir.join(format!("part{}", i)))
+            .collect::<Vec<_>>();
+        fs::write(&paths[0], b"0123").unwrap();
+        fs::write(&paths[1], b"").unwrap();
+        fs::write(&paths[2], b"456789").unwrap();
+
+        let mut reader = MultiVolume::from_paths(&paths).unwrap();
+        assert_eq!(&[0, 4, 4], reader.volume_starts());
+        assert_eq!(10, reader.len());
+        assert_eq!(b"0123456789".to_vec(), reader.read_exact(10).unwrap());
+
+        reader.seek(SeekFrom::Start(2)).unwrap();
+        assert_eq!(b"2345".to_vec(), reader.read_exact(4).unwrap());
+        reader.seek(SeekFrom::End(-3)).unwrap();
+        assert_eq!(b"789".to_vec(), reader.read_exact(3).unwrap());
+        assert!(reader.read_exact(1).is_err());
+
+        drop(reader);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,137 +1,322 @@
 use crate::attack::Attack;
 use crate::crc32_tab::CRC32TAB;
 use crate::keystream_tab::KEYSTREAMTAB;
-use crate::progress;
+use crate::utils::*;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem;
+#[cfg(feature = "parallel")]
 use rayon::prelude::*;
-use std::mem;
+
+/// Direction in which the keystream is walked by the Z reduction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// from Zi to Z{i-1}, using CRC32^-1
+    Backward,
+    /// from Zi to Z{i+1}, trying the 256 possible bytes of CRC32
+    Forward,
+}
 
 pub struct Zreduction<'a> {
     keystream: &'a [u8],
     zi_2_32_vector: Vec<u32>,
     index: usize,
+    direction: Direction,
+    /// maximum number of steps done by reduce
+    max_steps: usize,
+    /// one bit per Z[10,32) value, used to remove duplicates from big vectors
+    bitset: Vec<u64>,
+    /// tables of the forward steps, built the first time each keystream byte is met
+    forward_tabs: Vec<Option<ForwardTab>>,
+    wait_size: usize,
+    track_size: usize,
+    adaptive: bool,
+    /// (index, size) of each Z{i}[2,32) vector seen by reduce
+    curve: Vec<(usize, usize)>,
 }
 
 impl<'a> Zreduction<'a> {
-    // TODO: 自定义 WAIT_SIZE
-    const WAIT_SIZE: usize = 1 << 8;
-    const TRACK_SIZE: usize = 1 << 16;
+    /// default size under which reduce only waits a limited number of steps
+    pub const WAIT_SIZE: usize = 1 << 8;
+    /// default size under which reduce keeps track of the smallest vector
+    pub const TRACK_SIZE: usize = 1 << 16;
+    const BITSET_MIN_SIZE: usize = 1 << 16;
+    /// attacking one Z value costs about as much as reducing this many Z values,
+    /// the adaptive policy gives up once it reduced that many per remaining value
+    /// without a meaningful gain
+    const ADAPTIVE_WORK: usize = 1 << 16;
+    /// a gain is meaningful if the size shrinks by at least 1 / ADAPTIVE_GAIN
+    const ADAPTIVE_GAIN: usize = 8;
+    /// number of starting points tried in each direction by find_start
+    const PROBE_COUNT: usize = 8;
+    /// the probes of find_start only keep one Zi[16,32) value out of PROBE_SAMPLE
+    const PROBE_SAMPLE: u32 = 64;
+    /// number of steps done by each probe of find_start
+    const PROBE_STEPS: usize = 1 << 9;
 
-    pub fn new(keystream: &[u8]) -> Zreduction {
+    pub fn new(keystream: &'a [u8]) -> Zreduction<'a> {
         Zreduction {
             zi_2_32_vector: Vec::new(),
             keystream,
             index: 0,
+            direction: Direction::Backward,
+            max_steps: usize::MAX,
+            bitset: Vec::new(),
+            forward_tabs: Vec::new(),
+            wait_size: Zreduction::WAIT_SIZE,
+            track_size: Zreduction::TRACK_SIZE,
+            adaptive: false,
+            curve: Vec::new(),
         }
     }
 
+    /// set the size under which only a limited number of steps are waited for a smaller vector
+    pub fn set_wait_size(&mut self, wait_size: usize) {
+        self.wait_size = wait_size;
+    }
+
+    /// set the size under which the smallest vector is kept track of
+    pub fn set_track_size(&mut self, track_size: usize) {
+        self.track_size = track_size;
+    }
+
+    /// keep reducing only while the vector still shrinks meaningfully,
+    /// instead of waiting for a fixed number of steps
+    pub fn set_adaptive(&mut self, adaptive: bool) {
+        self.adaptive = adaptive;
+    }
+
+    /// generate all the Zi[2,32) values of the last index, to be reduced backward
     pub fn generate(&mut self) {
-        self.index = self.keystream.len();
-        self.zi_2_32_vector.reserve(1 << 22);
+        self.generate_from(self.keystream.len() - 1, Direction::Backward);
+    }
 
-        for &zi_2_16 in KEYSTREAMTAB
-            .get_zi_2_16_array(*self.keystream.last().unwrap())
-            .iter()
-        {
-            for high in 0..(1 << 16) {
+    /// generate all the Zi[2,32) values of the given index, to be reduced in the given direction
+    pub fn generate_from(&mut self, index: usize, direction: Direction) {
+        self.seed(index, direction, 1);
+    }
+
+    /// generate the Zi[2,32) values of the given index, keeping one Zi[16,32) out of sample
+    fn seed(&mut self, index: usize, direction: Direction, sample: u32) {
+        self.index = index;
+        self.direction = direction;
+        self.zi_2_32_vector.clear();
+        self.zi_2_32_vector.reserve((1 << 22) / sample as usize);
+
+        for &zi_2_16 in KEYSTREAMTAB.get_zi_2_16_array(self.keystream[index]).iter() {
+            for high in (0..(1 << 16)).step_by(sample as usize) {
                 self.zi_2_32_vector.push(high << 16 | zi_2_16);
             }
         }
     }
 
-    pub fn reduce(&mut self) {
+    /// **return** the index and direction from which the reduction is expected to give
+    /// the smallest vector, by reducing a sample of the Z values from a few starting points
+    pub fn find_start(&self) -> (usize, Direction) {
+        let first = Attack::SIZE - 1;
+        let last = self.keystream.len() - 1;
+
+        let mut starts = Vec::with_capacity(2 * Zreduction::PROBE_COUNT);
+        for k in 0..Zreduction::PROBE_COUNT {
+            let index = first + (last - first) * k / (Zreduction::PROBE_COUNT - 1);
+            // a start without any step to do cannot be judged
+            if index > first {
+                starts.push((index, Direction::Backward));
+            }
+            if index < last {
+                starts.push((index, Direction::Forward));
+            }
+        }
+
+        // the sampled values quickly spread over the same Z values as the full vector would,
+        // so the sizes of the probes can be compared as they are
+        let probe = |&(index, direction): &(usize, Direction)| {
+            let mut probe = Zreduction::new(self.keystream);
+            probe.set_wait_size(0);
+            probe.max_steps = Zreduction::PROBE_STEPS;
+            probe.seed(index, direction, Zreduction::PROBE_SAMPLE);
+            probe.reduce(|_, _| ());
+            probe.size()
+        };
+        #[cfg(feature = "parallel")]
+        let sizes = starts.par_iter().map(probe).collect::<Vec<_>>();
+        #[cfg(not(feature = "parallel"))]
+        let sizes = starts.iter().map(probe).collect::<Vec<_>>();
+
+        starts
+            .iter()
+            .zip(&sizes)
+            .min_by_key(|&(_, &size)| size)
+            .map_or((last, Direction::Backward), |(&start, _)| start)
+    }
+
+    /// reduce the Zi[2,32) values using the extra keystream bytes,
+    /// progress is called with the number of done and total steps
+    pub fn reduce<F: FnMut(usize, usize)>(&mut self, mut progress: F) {
         // variables to keep track of the smallest Zi[2,32) vector
         let mut tracking = false;
         let mut best_copy = Vec::with_capacity(1 << 16);
-        let (mut best_index, mut best_size) = (0usize, Zreduction::TRACK_SIZE);
+        let (mut best_index, mut best_size) = (0usize, self.track_size);
 
         // variables to wait for a limited number of steps when a small enough vector is found
         let mut waiting = false;
         let mut wait = 0usize;
 
+        // size at the last meaningful gain and work done since, for the adaptive policy
+        let (mut gain_size, mut work) = (usize::MAX, 0usize);
+
+        // index of the vector in zi_2_32_vector
+        let mut last_index = self.index;
+
+        let total = match self.direction {
+            Direction::Backward => self.index.saturating_sub(Attack::SIZE - 1),
+            Direction::Forward => self.keystream.len() - 1 - self.index,
+        }
+        .min(self.max_steps);
+
         let mut zim1_10_32_vector = Vec::with_capacity(1 << 16);
-        let mut zim1_2_32_vector = Vec::with_capacity(1 << 16);
-
-        for i in (Attack::SIZE..self.index).rev() {
-            zim1_10_32_vector.clear();
-            zim1_2_32_vector.clear();
-
-            // generate the Z{i-1}[10,32) values
-            for &zi_2_32 in &self.zi_2_32_vector {
-                // get Z{i-1}[10,32) from CRC32^-1
-                let zim1_10_32 = CRC32TAB.get_zim1_10_32(zi_2_32);
-                // collect only those that are compatible with keystream{i-1}
-                if KEYSTREAMTAB.has_zi_2_16(self.keystream[i - 1], zim1_10_32) {
-                    zim1_10_32_vector.push(zim1_10_32);
-                }
-            }
+        let mut next_vector = Vec::with_capacity(1 << 16);
 
-            // remove duplicates
-            if zim1_10_32_vector.len() >= (1 << 12) {
-                // 如果 Vec 本身不大, 并行排序可能反而降低效率
-                zim1_10_32_vector.par_sort_unstable();
-            } else {
-                zim1_10_32_vector.sort_unstable();
-            }
+        self.curve.clear();
+        self.curve.push((self.index, self.zi_2_32_vector.len()));
 
-            zim1_10_32_vector.dedup();
+        for step in 0..total {
+            // the vector at index i is computed from the one at index prev
+            let prev = last_index;
+            let i = match self.direction {
+                Direction::Backward => prev - 1,
+                Direction::Forward => prev + 1,
+            };
 
-            // complete Z{i-1}[10,32) values up to Z{i-1}[2,32)
-            for &zim1_10_32 in &zim1_10_32_vector {
-                // get Z{i-1}[2,16) values from keystream byte k{i-1} and Z{i-1}[10,16)
-                for &zim1_2_16 in KEYSTREAMTAB.get_zi_2_16_vector(self.keystream[i - 1], zim1_10_32)
-                {
-                    //println!("({} {})", zi_2_32, zim1_10_32);
-                    zim1_2_32_vector.push(zim1_10_32 | zim1_2_16);
+            next_vector.clear();
+            match self.direction {
+                Direction::Backward => {
+                    // generate the Z{i}[10,32) values
+                    zim1_10_32_vector.clear();
+                    filter(
+                        &self.zi_2_32_vector,
+                        self.keystream[i],
+                        &mut zim1_10_32_vector,
+                    );
+
+                    // remove duplicates
+                    self.dedup(&mut zim1_10_32_vector);
+
+                    // complete Z{i}[10,32) values up to Z{i}[2,32)
+                    for &zim1_10_32 in &zim1_10_32_vector {
+                        // get Z{i}[2,16) values from keystream byte k{i} and Z{i}[10,16)
+                        for &zim1_2_16 in
+                            KEYSTREAMTAB.get_zi_2_16_vector(self.keystream[i], zim1_10_32)
+                        {
+                            next_vector.push(zim1_10_32 | zim1_2_16);
+                        }
+                    }
+                }
+                Direction::Forward => {
+                    if self.forward_tabs.is_empty() {
+                        self.forward_tabs.resize_with(256, || None);
+                    }
+                    let kip1 = self.keystream[i];
+                    self.forward_tabs[kip1 as usize]
+                        .get_or_insert_with(|| ForwardTab::new(kip1))
+                        .step(&self.zi_2_32_vector, &mut next_vector);
+
+                    // remove duplicates
+                    Self::sort(&mut next_vector);
+                    next_vector.dedup();
                 }
             }
-            //std::process::exit(1);
+            self.curve.push((i, next_vector.len()));
 
-            // update smallest vector tracking
-            if zim1_2_32_vector.len() <= best_size {
+            // update smallest vector tracking, the attack needs Attack::SIZE bytes up to i
+            let mut stop = false;
+            if i < Attack::SIZE - 1 {
+                // not usable yet
+            } else if next_vector.len() <= best_size {
                 tracking = true;
-                best_index = i - 1;
-                best_size = zim1_2_32_vector.len();
+                best_index = i;
+                best_size = next_vector.len();
                 waiting = false;
             } else if tracking {
                 // vector is bigger than bestSize
-                if best_index == i {
+                if best_index == prev {
                     // hit a minimum
                     // keep a copy of the vector because size is about to grow
-                    std::mem::swap(&mut best_copy, &mut self.zi_2_32_vector);
+                    mem::swap(&mut best_copy, &mut self.zi_2_32_vector);
 
-                    if best_size <= Zreduction::WAIT_SIZE {
+                    if best_size <= self.wait_size && !self.adaptive {
                         // enable waiting
                         waiting = true;
                         wait = best_size * 4;
                     }
                 }
 
-                wait -= 1;
-                if waiting && wait == 0 {
-                    break;
+                if waiting {
+                    wait -= 1;
+                    stop = wait == 0;
+                }
+            }
+
+            // stop once reducing further costs more than what it saves to the attack
+            if self.adaptive && tracking {
+                if best_size <= gain_size - gain_size / Zreduction::ADAPTIVE_GAIN {
+                    gain_size = best_size;
+                    work = 0;
                 }
+                work += next_vector.len();
+                stop = work >= gain_size.saturating_mul(Zreduction::ADAPTIVE_WORK);
             }
 
             // put result in z_2_32_vector
-            mem::swap(&mut self.zi_2_32_vector, &mut zim1_2_32_vector);
-            // self.zi_2_32_vector = zim1_2_32_vector;
-            let now = self.keystream.len() - i;
-            let total = self.keystream.len() - Attack::SIZE;
-            progress(now, total);
+            mem::swap(&mut self.zi_2_32_vector, &mut next_vector);
+            last_index = i;
+            progress(step + 1, total);
+
+            if stop {
+                break;
+            }
         }
 
         if tracking {
             // put bestCopy in z_2_32_vector only if bestIndex is not the index of z_2_32_vector
-            if best_index != Attack::SIZE - 1 {
+            if best_index != last_index {
                 mem::swap(&mut self.zi_2_32_vector, &mut best_copy);
-                //self.zi_2_32_vector = best_copy;
             }
             self.index = best_index;
         } else {
-            self.index = Attack::SIZE - 1;
+            self.index = last_index;
+        }
+    }
+
+    /// sort the Z[10,32) values and remove duplicates
+    fn dedup(&mut self, vector: &mut Vec<u32>) {
+        // walking through the bitset costs as much as sorting 1 << 16 values
+        if vector.len() >= Zreduction::BITSET_MIN_SIZE {
+            if self.bitset.is_empty() {
+                self.bitset = vec![0; 1 << 16];
+            }
+            dedup_bitset(vector, &mut self.bitset);
+        } else {
+            Self::sort(vector);
+            vector.dedup();
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    fn sort(vector: &mut [u32]) {
+        if vector.len() >= (1 << 16) {
+            // 如果 Vec 本身不大, 并行排序可能反而降低效率
+            vector.par_sort_unstable();
+        } else {
+            vector.sort_unstable();
         }
     }
 
+    #[cfg(not(feature = "parallel"))]
+    fn sort(vector: &mut [u32]) {
+        vector.sort_unstable();
+    }
+
     pub fn size(&self) -> usize {
         self.zi_2_32_vector.len()
     }
@@ -143,4 +328,320 @@ impl<'a> Zreduction<'a> {
     pub fn get_zi_2_32_vector(&self) -> &Vec<u32> {
         &self.zi_2_32_vector
     }
+
+    /// **return** the (index, size) of each Zi[2,32) vector seen by the last reduce,
+    /// in the order they were computed, from the start index to where it stopped
+    pub fn get_curve(&self) -> &[(usize, usize)] {
+        &self.curve
+    }
+}
+
+/// compute Z{i-1}[10,32) from each Zi[2,32) value and keep those compatible with ki
+fn filter(zi_2_32_vector: &[u32], ki: u8, zim1_10_32_vector: &mut Vec<u32>) {
+    #[cfg(all(feature = "std", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 is supported by the CPU
+            unsafe { avx2::filter(zi_2_32_vector, ki, zim1_10_32_vector) };
+            return;
+        }
+    }
+    filter_scalar(zi_2_32_vector, ki, zim1_10_32_vector);
+}
+
+/// Bytes b of CRC32 grouped by (Z{i+1} ^ crctab[b])[2,16) for the Z{i+1}[2,16) values giving k{i+1}.
+/// Z{i+1} = Zi[8,32) >> 8 ^ crctab[b] for some unknown byte b, so that Zi[8,32) directly
+/// gives the bytes leading to a Z{i+1} compatible with k{i+1}
+struct ForwardTab {
+    /// the bytes for Zi[10,24) equal to t are bytes[offsets[t]..offsets[t + 1]]
+    offsets: Vec<u16>,
+    bytes: Vec<u8>,
+}
+
+impl ForwardTab {
+    fn new(kip1: u8) -> ForwardTab {
+        let zip1_2_16_array = KEYSTREAMTAB.get_zi_2_16_array(kip1);
+        let index = |zip1_2_16: u32, b: u8| ((zip1_2_16 ^ CRC32TAB.crc32(0, b)) & MASK_2_16) >> 2;
+
+        // count the bytes of each group, then turn the sizes into offsets
+        let mut offsets = vec![0u16; (1 << 14) + 1];
+        for &zip1_2_16 in &zip1_2_16_array {
+            for b in 0..=255 {
+                offsets[index(zip1_2_16, b) as usize + 1] += 1;
+            }
+        }
+        for t in 0..1 << 14 {
+            offsets[t + 1] += offsets[t];
+        }
+
+        // fill the groups
+        let mut fill = offsets.clone();
+        let mut bytes = vec![0u8; 1 << 14];
+        for &zip1_2_16 in &zip1_2_16_array {
+            for b in 0..=255 {
+                let t = index(zip1_2_16, b) as usize;
+                bytes[fill[t] as usize] = b;
+                fill[t] += 1;
+            }
+        }
+
+        ForwardTab { offsets, bytes }
+    }
+
+    /// compute the Z{i+1}[2,32) values compatible with k{i+1} from each Zi[2,32) value
+    fn step(&self, zi_2_32_vector: &[u32], zip1_2_32_vector: &mut Vec<u32>) {
+        for &zi_2_32 in zi_2_32_vector {
+            let zi_8_32 = zi_2_32 & MASK_8_32;
+            let t = ((zi_8_32 >> 8 & MASK_2_16) >> 2) as usize;
+            let (start, end) = (self.offsets[t] as usize, self.offsets[t + 1] as usize);
+            for &b in &self.bytes[start..end] {
+                zip1_2_32_vector.push(CRC32TAB.crc32(zi_8_32, b) & MASK_2_32);
+            }
+        }
+    }
+}
+
+fn filter_scalar(zi_2_32_vector: &[u32], ki: u8, zim1_10_32_vector: &mut Vec<u32>) {
+    for &zi_2_32 in zi_2_32_vector {
+        // get Z{i-1}[10,32) from CRC32^-1
+        let zim1_10_32 = CRC32TAB.get_zim1_10_32(zi_2_32);
+        // collect only those that are compatible with keystream{i-1}
+        if KEYSTREAMTAB.has_zi_2_16(ki, zim1_10_32) {
+            zim1_10_32_vector.push(zim1_10_32);
+        }
+    }
+}
+
+#[cfg(all(feature = "std", target_arch = "x86_64"))]
+mod avx2 {
+    use super::filter_scalar;
+    use crate::crc32_tab::CRC32TAB;
+    use crate::keystream_tab::KEYSTREAMTAB;
+    use crate::utils::*;
+    use alloc::vec::Vec;
+    use core::arch::x86_64::*;
+
+    /// same as filter_scalar, 8 values at a time
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn filter(zi_2_32_vector: &[u32], ki: u8, zim1_10_32_vector: &mut Vec<u32>) {
+        let crcinvtab = CRC32TAB.get_crcinvtab();
+        let mask = KEYSTREAMTAB.get_zi_10_16_bitset(ki);
+        let mask_lo = _mm256_set1_epi32(mask as i32);
+        let mask_hi = _mm256_set1_epi32((mask >> 32) as i32);
+        let mask_10_32 = _mm256_set1_epi32(MASK_10_32 as i32);
+        let mask_0_6 = _mm256_set1_epi32(0x3f);
+        let one = _mm256_set1_epi32(1);
+        let thirty_two = _mm256_set1_epi32(32);
+
+        let chunks = zi_2_32_vector.chunks_exact(8);
+        let rest = chunks.remainder();
+        let mut values = [0u32; 8];
+        for chunk in chunks {
+            let zi_2_32 = _mm256_loadu_si256(chunk.as_ptr() as *const __m256i);
+
+            // Z{i-1}[10,32) = (Zi << 8 ^ crcinvtab[msb(Zi)]) & MASK_10_32
+            let msb = _mm256_srli_epi32(zi_2_32, 24);
+            let inv = _mm256_i32gather_epi32(crcinvtab.as_ptr() as *const i32, msb, 4);
+            let zim1_10_32 = _mm256_and_si256(
+                _mm256_xor_si256(_mm256_slli_epi32(zi_2_32, 8), inv),
+                mask_10_32,
+            );
+
+            // test bit Z{i-1}[10,16) of the mask, shifts by 32 or more give 0
+            let j = _mm256_and_si256(_mm256_srli_epi32(zim1_10_32, 10), mask_0_6);
+            let bit = _mm256_or_si256(
+                _mm256_srlv_epi32(mask_lo, j),
+                _mm256_srlv_epi32(mask_hi, _mm256_sub_epi32(j, thirty_two)),
+            );
+            let keep = _mm256_cmpeq_epi32(_mm256_and_si256(bit, one), one);
+            let mut keep = _mm256_movemask_ps(_mm256_castsi256_ps(keep)) as u32;
+
+            // collect the values that are kept
+            _mm256_storeu_si256(values.as_mut_ptr() as *mut __m256i, zim1_10_32);
+            while keep != 0 {
+                zim1_10_32_vector.push(values[keep.trailing_zeros() as usize]);
+                keep &= keep - 1;
+            }
+        }
+
+        filter_scalar(rest, ki, zim1_10_32_vector);
+    }
+}
+
+/// sort the Z[10,32) values and remove duplicates using a bitset of 1 << 16 words
+fn dedup_bitset(vector: &mut Vec<u32>, bitset: &mut [u64]) {
+    for &z_10_32 in vector.iter() {
+        bitset[(z_10_32 >> 16) as usize] |= 1 << ((z_10_32 >> 10) & 0x3f);
+    }
+
+    // walk through the bitset in ascending order and clear it for the next use
+    vector.clear();
+    for (i, word) in bitset.iter_mut().enumerate() {
+        let mut bits = mem::replace(word, 0);
+        while bits != 0 {
+            vector.push(((i as u32) << 6 | bits.trailing_zeros()) << 10);
+            bits &= bits - 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dedup_bitset, filter, filter_scalar, Direction, ForwardTab, Zreduction};
+    use crate::attack::Attack;
+    use crate::crc32_tab::CRC32TAB;
+    use crate::keys::Keys;
+    use crate::keystream_tab::KEYSTREAMTAB;
+    use crate::utils::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    /// keystream and Zi[2,32) values of the keys 12345678 23456789 34567890
+    fn keystream(len: usize) -> (Vec<u8>, Vec<u32>) {
+        let mut keys = Keys::new();
+        keys.set_keys(0x1234_5678, 0x2345_6789, 0x3456_7890);
+        (0..len)
+            .map(|i| {
+                let z = keys.get_z();
+                keys.update(i as u8);
+                (KEYSTREAMTAB.get_byte(z), z & 0xffff_fffc)
+            })
+            .unzip()
+    }
+
+    #[test]
+    #[ignore]
+    fn reduce_keeps_z() {
+        let (keystream, zs) = keystream(1 << 9);
+        let starts = [
+            (keystream.len() - 1, Direction::Backward),
+            (0, Direction::Forward),
+            (keystream.len() / 2, Direction::Forward),
+        ];
+        for &(start, direction) in &starts {
+            for &adaptive in &[false, true] {
+                let mut zr = Zreduction::new(&keystream);
+                zr.set_adaptive(adaptive);
+                zr.generate_from(start, direction);
+                zr.reduce(|_, _| ());
+
+                assert!(zr.size() < 1 << 16);
+                assert!(zr.get_index() >= 11);
+                assert!(zr.get_zi_2_32_vector().contains(&zs[zr.get_index()]));
+
+                let curve = zr.get_curve();
+                assert_eq!((start, 1 << 22), curve[0]);
+                assert!(curve.contains(&(zr.get_index(), zr.size())));
+            }
+        }
+    }
+
+    #[test]
+    fn reduce_sample_keeps_z() {
+        let (keystream, zs) = keystream(1 << 6);
+        let starts = [
+            (keystream.len() - 1, Direction::Backward),
+            (Attack::SIZE - 1, Direction::Forward),
+            (keystream.len() / 2, Direction::Forward),
+            (keystream.len() / 2, Direction::Backward),
+        ];
+        for &(start, direction) in &starts {
+            for &adaptive in &[false, true] {
+                // only a sample of the values as in find_start, plus the right one
+                let mut zr = Zreduction::new(&keystream);
+                zr.set_adaptive(adaptive);
+                zr.max_steps = 1 << 4;
+                zr.seed(start, direction, Zreduction::PROBE_SAMPLE << 4);
+                zr.zi_2_32_vector.push(zs[start]);
+                let size = zr.size();
+                zr.reduce(|_, _| ());
+
+                assert!(zr.get_index() >= Attack::SIZE - 1);
+                assert!(zr.get_zi_2_32_vector().contains(&zs[zr.get_index()]));
+
+                let curve = zr.get_curve();
+                assert_eq!((start, size), curve[0]);
+                assert!(curve.len() > 1);
+                assert!(curve.contains(&(zr.get_index(), zr.size())));
+            }
+        }
+    }
+
+    #[test]
+    fn find_start() {
+        // a short keystream keeps the probes cheap
+        let (keystream, _) = keystream(Attack::SIZE + 8);
+        let (index, direction) = Zreduction::new(&keystream).find_start();
+        assert!(index >= Attack::SIZE - 1 && index < keystream.len());
+        match direction {
+            Direction::Backward => assert!(index > Attack::SIZE - 1),
+            Direction::Forward => assert!(index < keystream.len() - 1),
+        }
+    }
+
+    /// pseudo random Zi[2,32) values
+    fn values(len: usize) -> Vec<u32> {
+        let mut x = 0x1234_5678u32;
+        (0..len)
+            .map(|_| {
+                x = x.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                x & 0xffff_fffc
+            })
+            .collect()
+    }
+
+    #[test]
+    fn filter_same_as_scalar() {
+        let zi_2_32_vector = values(10_003);
+        for &ki in &[0, 1, 0x5a, 0xff] {
+            let (mut expected, mut actual) = (vec![], vec![]);
+            filter_scalar(&zi_2_32_vector, ki, &mut expected);
+            filter(&zi_2_32_vector, ki, &mut actual);
+            assert!(!expected.is_empty());
+            assert_eq!(expected, actual);
+        }
+    }
+
+    /// compute the Z{i+1}[2,32) values by trying the 256 bytes of CRC32
+    fn step_forward(zi_2_32_vector: &[u32], kip1: u8, zip1_2_32_vector: &mut Vec<u32>) {
+        for &zi_2_32 in zi_2_32_vector {
+            for b in 0..=255 {
+                let zip1 = CRC32TAB.crc32(zi_2_32 & MASK_8_32, b);
+                if KEYSTREAMTAB.get_byte(zip1) == kip1 {
+                    zip1_2_32_vector.push(zip1 & MASK_2_32);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn forward_tab_same_as_step_forward() {
+        let zi_2_32_vector = values(10_003);
+        for &kip1 in &[0, 1, 0x5a, 0xff] {
+            let (mut expected, mut actual) = (vec![], vec![]);
+            step_forward(&zi_2_32_vector, kip1, &mut expected);
+            ForwardTab::new(kip1).step(&zi_2_32_vector, &mut actual);
+            expected.sort_unstable();
+            actual.sort_unstable();
+            assert!(!expected.is_empty());
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn dedup_same_as_sort() {
+        let mut expected = values(100_000)
+            .into_iter()
+            .map(|z| z & 0xffff_fc00)
+            .collect::<Vec<_>>();
+        expected.extend_from_slice(&expected.clone()[..1000]);
+        let mut actual = expected.clone();
+        expected.sort_unstable();
+        expected.dedup();
+
+        let mut bitset = vec![0; 1 << 16];
+        dedup_bitset(&mut actual, &mut bitset);
+        assert_eq!(expected, actual);
+        assert!(bitset.iter().all(|&word| word == 0));
+    }
 }